@@ -0,0 +1,163 @@
+use std::hint::black_box;
+use std::io::BufRead;
+use std::time::{Duration, Instant};
+
+use minigrep::context::Line;
+use minigrep::literal::{self, Finder};
+use minigrep::matcher::Matcher;
+use minigrep::searcher::Searcher;
+use minigrep::Match;
+
+// Compares the whole-buffer literal search against the way minigrep used
+// to search: splitting the text into lines and calling str::contains and
+// str::match_indices on each one. Both the in-memory search and the
+// streaming Searcher, which is what searching files goes through, are
+// timed.
+
+// The old per-line search, kept here for comparison. It finds the same
+// matches and builds the same Match for each line, so the only
+// difference is how the text is searched.
+fn search_by_line<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    let mut offset = 0;
+    contents
+        .split_inclusive('\n')
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let start = offset;
+            offset += line.len();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let spans = line
+                .match_indices(query)
+                .map(|(i, m)| (i, i + m.len(), 0))
+                .collect();
+            Match::new(line, index + 1, start, spans)
+        })
+        .collect()
+}
+
+// The old streaming search, reading a line at a time and searching each
+// one, as Searcher still does when it needs context or -v. Returns the
+// line number and byte offset of each matching line.
+fn stream_by_line(matcher: &Matcher, mut reader: impl BufRead) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+    let mut offset = 0;
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer).unwrap();
+        if read == 0 {
+            return found;
+        }
+        line_number += 1;
+        let bytes = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        let line = String::from_utf8_lossy(bytes);
+        if let Some(m) = Match::find(matcher, &line, line_number, offset) {
+            found.push((m.line_number, m.byte_offset));
+        }
+        offset += read;
+    }
+}
+
+fn stream(searcher: &Searcher, reader: impl BufRead) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    searcher
+        .search_reader(reader, |line| {
+            if let Line::Match(m) = line {
+                found.push((m.line_number, m.byte_offset));
+            }
+            Ok(())
+        })
+        .unwrap();
+    found
+}
+
+// Some text that looks a bit like prose, always the same for the same size.
+fn text(bytes: usize) -> String {
+    let words = [
+        "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "and", "a", "rust",
+        "borrow", "checker", "lifetime", "of", "to", "in", "is", "it", "that",
+    ];
+    let mut seed: u32 = 12345;
+    let mut text = String::with_capacity(bytes + 100);
+    let mut line = 0;
+    while text.len() < bytes {
+        // A simple linear congruential generator is random enough here.
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let word = words[(seed >> 16) as usize % words.len()];
+        text.push_str(word);
+        line += word.len() + 1;
+        if line > 70 {
+            text.push('\n');
+            line = 0;
+        } else {
+            text.push(' ');
+        }
+    }
+    text
+}
+
+// Runs f repeatedly for about a second and returns the fastest run.
+fn time<T>(mut f: impl FnMut() -> Vec<T>) -> (Duration, Vec<T>) {
+    let started = Instant::now();
+    let mut best = Duration::MAX;
+    let mut result = Vec::new();
+    while started.elapsed() < Duration::from_secs(1) {
+        let start = Instant::now();
+        result = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    (best, result)
+}
+
+// From never found, through rare, to on almost every line.
+const QUERIES: [&str; 6] = ["xylophone", "zebra", "checker lifetime", "fox", "rust", "e"];
+
+fn main() {
+    let contents = text(32 * 1024 * 1024);
+    let mb = contents.len() as f64 / (1024.0 * 1024.0);
+    println!("searching {mb:.0} MB of text\n");
+    println!("in memory:");
+    println!(
+        "{:<16} {:>8} {:>12} {:>12} {:>8}",
+        "query", "lines", "per line", "whole", "speedup"
+    );
+
+    for query in QUERIES {
+        let finder = Finder::new(query);
+        let (old, old_lines) = time(|| search_by_line(query, &contents));
+        let (new, new_lines) = time(|| literal::search(&finder, &contents));
+        assert!(old_lines == new_lines, "the searches disagree on {query:?}");
+        println!(
+            "{query:<16} {:>8} {:>9.0} MB/s {:>7.0} MB/s {:>7.1}x",
+            new_lines.len(),
+            mb / old.as_secs_f64(),
+            mb / new.as_secs_f64(),
+            old.as_secs_f64() / new.as_secs_f64(),
+        );
+    }
+
+    // The reader is the text itself, so this times the searching rather
+    // than the disk.
+    println!("\nstreaming:");
+    println!(
+        "{:<16} {:>8} {:>12} {:>12} {:>8}",
+        "query", "lines", "per line", "whole", "speedup"
+    );
+    for query in QUERIES {
+        let matcher = Matcher::literal(query);
+        let searcher = Searcher::new(&matcher, 0, 0);
+        let (old, old_lines) = time(|| stream_by_line(&matcher, contents.as_bytes()));
+        let (new, new_lines) = time(|| stream(&searcher, contents.as_bytes()));
+        assert!(old_lines == new_lines, "the searches disagree on {query:?}");
+        println!(
+            "{query:<16} {:>8} {:>9.0} MB/s {:>7.0} MB/s {:>7.1}x",
+            new_lines.len(),
+            mb / old.as_secs_f64(),
+            mb / new.as_secs_f64(),
+            old.as_secs_f64() / new.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use crate::fold::{self, fold};
+
+// Searching for many literal patterns at once, with the Aho-Corasick
+// algorithm. Searching for each pattern in turn would read every line once
+// per pattern; this reads each line once however many patterns there are.
+//
+// The patterns are first put into a trie, a tree where each edge is a byte
+// and each pattern is a path down from the root. Walking the trie as we
+// read the text tells us which patterns the text so far could be the start
+// of. When the next byte has no edge to follow, rather than starting again
+// from the root we jump to the state for the longest suffix of what we've
+// read that is also in the trie. Working those jumps out in advance and
+// filling them in as ordinary edges turns the trie into a state machine
+// that takes exactly one step per byte of text.
+
+const ROOT: usize = 0;
+
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    // transitions[state * 256 + byte] is the state to move to from state
+    // on reading byte.
+    transitions: Vec<u32>,
+    // The patterns that have just been read in full on arriving at each
+    // state, including ones that are a suffix of a longer pattern.
+    outputs: Vec<Vec<usize>>,
+    // The length in bytes of each pattern, after case folding.
+    lengths: Vec<usize>,
+    ignore_case: bool,
+    // An empty pattern matches every line, so it is kept out of the trie.
+    empty: Option<usize>,
+}
+
+impl AhoCorasick {
+    pub fn new<S: AsRef<str>>(patterns: &[S], ignore_case: bool) -> AhoCorasick {
+        let mut transitions = vec![0; 256];
+        let mut outputs = vec![Vec::new()];
+        let mut lengths = Vec::new();
+        let mut empty = None;
+
+        // While building the trie, 0 means there is no edge yet. That can't
+        // be confused with an edge back to the root, since nothing leads
+        // back to the root in a trie.
+        for (id, pattern) in patterns.iter().enumerate() {
+            let pattern = match ignore_case {
+                true => fold::fold_str(pattern.as_ref()),
+                false => pattern.as_ref().to_string(),
+            };
+            lengths.push(pattern.len());
+            if pattern.is_empty() {
+                empty.get_or_insert(id);
+                continue;
+            }
+            let mut state = ROOT;
+            for &b in pattern.as_bytes() {
+                let edge = state * 256 + b as usize;
+                if transitions[edge] == 0 {
+                    transitions[edge] = outputs.len() as u32;
+                    transitions.extend_from_slice(&[0; 256]);
+                    outputs.push(Vec::new());
+                }
+                state = transitions[edge] as usize;
+            }
+            outputs[state].push(id);
+        }
+
+        // Going through the states breadth first means that the fallback
+        // for a state, which is always nearer the root, is finished before
+        // the state itself is looked at. The missing edges of the root
+        // already lead back to the root, which is what we want.
+        let mut fallback = vec![ROOT; outputs.len()];
+        let mut queue: VecDeque<usize> = (0..256)
+            .map(|b| transitions[b] as usize)
+            .filter(|&child| child != ROOT)
+            .collect();
+        while let Some(state) = queue.pop_front() {
+            let inherited = outputs[fallback[state]].clone();
+            outputs[state].extend(inherited);
+            for b in 0..256 {
+                let edge = state * 256 + b;
+                let instead = transitions[fallback[state] * 256 + b];
+                match transitions[edge] as usize {
+                    ROOT => transitions[edge] = instead,
+                    child => {
+                        fallback[child] = instead as usize;
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+
+        AhoCorasick {
+            transitions,
+            outputs,
+            lengths,
+            ignore_case,
+            empty,
+        }
+    }
+
+    // Returns (start, end, pattern) for the non-overlapping matches in
+    // line, where pattern is the index of the pattern that matched. Where
+    // matches overlap, the one starting first wins, then the longest.
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize, usize)> {
+        let mut found = self.find_overlapping(line);
+        let mut end_of_last = 0;
+        found.retain(|&(start, end, _)| {
+            let keep = start >= end_of_last;
+            if keep {
+                end_of_last = end;
+            }
+            keep
+        });
+        found
+    }
+
+    // Like find_all, but keeps every match, including those that overlap
+    // each other, in the order find_all would pick them.
+    pub fn find_overlapping(&self, line: &str) -> Vec<(usize, usize, usize)> {
+        let mut found = Vec::new();
+        if let Some(id) = self.empty {
+            found.push((0, 0, id));
+        }
+        if self.ignore_case {
+            self.find_folded(line, &mut found);
+        } else {
+            let mut state = ROOT;
+            for (i, &b) in line.as_bytes().iter().enumerate() {
+                state = self.transitions[state * 256 + b as usize] as usize;
+                for &id in &self.outputs[state] {
+                    found.push((i + 1 - self.lengths[id], i + 1, id));
+                }
+            }
+        }
+        found.sort_by_key(|&(start, end, id)| (start, Reverse(end), id));
+        found
+    }
+
+    // Whether one of the patterns is empty, which matches anywhere.
+    pub fn has_empty(&self) -> bool {
+        self.empty.is_some()
+    }
+
+    // Like find_overlapping, but runs the case folded form of line through the
+    // state machine. As with the single pattern search in fold.rs, only
+    // matches covering whole characters of line count.
+    fn find_folded(&self, line: &str, found: &mut Vec<(usize, usize, usize)>) {
+        // Where in line each folded byte came from, if it is the first
+        // byte of the folded form of a character.
+        let mut starts = Vec::with_capacity(line.len());
+        let mut state = ROOT;
+        let mut buf = [0; 4];
+        for (i, c) in line.char_indices() {
+            let mut first = true;
+            for folded in fold(c) {
+                for &b in folded.encode_utf8(&mut buf).as_bytes() {
+                    starts.push(first.then_some(i));
+                    first = false;
+                    state = self.transitions[state * 256 + b as usize] as usize;
+                }
+            }
+            let end = i + c.len_utf8();
+            for &id in &self.outputs[state] {
+                if let Some(start) = starts[starts.len() - self.lengths[id]] {
+                    found.push((start, end, id));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_pattern_in_one_pass() {
+        let ac = AhoCorasick::new(&["he", "she", "his", "hers"], false);
+        assert_eq!(vec![(1, 4, 1)], ac.find_all("ushers"));
+        assert_eq!(vec![(0, 2, 0), (3, 6, 2)], ac.find_all("he his"));
+        assert_eq!(vec![(0, 4, 3)], ac.find_all("hers"));
+        assert!(ac.find_all("nothing").is_empty());
+    }
+
+    #[test]
+    fn prefers_the_leftmost_then_longest() {
+        let ac = AhoCorasick::new(&["abc", "b", "abcd", "cde"], false);
+        assert_eq!(vec![(0, 4, 2)], ac.find_all("abcde"));
+        assert_eq!(vec![(1, 2, 1)], ac.find_all("xbcd"));
+    }
+
+    #[test]
+    fn ignores_case_and_empty_patterns() {
+        let ac = AhoCorasick::new(&["STRASSE", "evil.com"], true);
+        assert_eq!(vec![(0, 7, 0), (8, 16, 1)], ac.find_all("Straße EVIL.com"));
+        // The 's' alone would end part way through the 'ß'.
+        let ac = AhoCorasick::new(&["s"], true);
+        assert!(ac.find_all("ß").is_empty());
+
+        let ac = AhoCorasick::new(&["", "x"], false);
+        assert_eq!(vec![(0, 0, 0)], ac.find_all("abc"));
+        assert_eq!(vec![(0, 1, 1)], ac.find_all("xyz"));
+    }
+}
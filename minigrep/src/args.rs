@@ -0,0 +1,939 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::encoding::Encoding;
+use crate::error::MinigrepError;
+use crate::types::Types;
+use crate::BinaryFiles;
+
+// The command-line parser behind Config::build.
+// Options are described once in the OPTIONS table, which is used both to
+// parse the arguments and to print the help text, so the two can't drift
+// apart. Parsing only works out which options were given and with what
+// values; Config::build decides what they mean.
+//
+// The usual conventions are supported:
+//   -i, --ignore-case       short and long forms
+//   -in                     short flags combined into one argument
+//   -A 3, -A3, -inA3        short options with values, separate or attached
+//   --context 3, --context=3
+//   --                      everything after this is positional,
+//                           so queries can start with '-'
+//   --no-line-number        undoes an earlier --line-number, and the same
+//                           for any other option without a value
+//
+// Options can also be given ahead of time, in a config file and in the
+// MINIGREP_OPTS environment variable; see with_defaults.
+
+pub(crate) struct Opt {
+    pub short: Option<char>,
+    pub long: &'static str,
+    // The placeholder shown in the help text for options that take a
+    // value, or None for simple on/off flags.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub(crate) const OPTIONS: &[Opt] = &[
+    Opt {
+        short: Some('i'),
+        long: "ignore-case",
+        value: None,
+        help: "match without regard to case",
+    },
+    Opt {
+        short: Some('s'),
+        long: "case-sensitive",
+        value: None,
+        help: "match case exactly, even if IGNORE_CASE is set",
+    },
+    Opt {
+        short: Some('S'),
+        long: "smart-case",
+        value: None,
+        help: "ignore case unless the query has an uppercase letter",
+    },
+    Opt {
+        short: Some('E'),
+        long: "regex",
+        value: None,
+        help: "treat the query as a regular expression",
+    },
+    Opt {
+        short: Some('e'),
+        long: "regexp",
+        value: Some("PATTERN"),
+        help: "search for PATTERN; can be given more than once",
+    },
+    Opt {
+        short: Some('f'),
+        long: "file",
+        value: Some("FILE"),
+        help: "search for each of the patterns in FILE, one per line",
+    },
+    Opt {
+        short: None,
+        long: "show-pattern",
+        value: None,
+        help: "print which patterns matched each line",
+    },
+    Opt {
+        short: None,
+        long: "fuzzy",
+        value: Some("NUM"),
+        help: "match text up to NUM typos away from the query",
+    },
+    Opt {
+        short: Some('w'),
+        long: "word-regexp",
+        value: None,
+        help: "only match whole words",
+    },
+    Opt {
+        short: Some('x'),
+        long: "line-regexp",
+        value: None,
+        help: "only match whole lines",
+    },
+    Opt {
+        short: Some('v'),
+        long: "invert-match",
+        value: None,
+        help: "select the lines that don't match",
+    },
+    Opt {
+        short: Some('m'),
+        long: "max-count",
+        value: Some("NUM"),
+        help: "stop reading a file after NUM matching lines",
+    },
+    Opt {
+        short: Some('c'),
+        long: "count",
+        value: None,
+        help: "print only the number of matching lines in each file",
+    },
+    Opt {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: None,
+        help: "print only the names of files with a match",
+    },
+    Opt {
+        short: Some('L'),
+        long: "files-without-match",
+        value: None,
+        help: "print only the names of files without a match",
+    },
+    // No short form, since grep users would expect -r to mean searching
+    // directories, and minigrep already does that whenever it is given one.
+    Opt {
+        short: None,
+        long: "replace",
+        value: Some("TEXT"),
+        help: "print lines with each match replaced by TEXT; $1 is group 1",
+    },
+    Opt {
+        short: None,
+        long: "in-place",
+        value: None,
+        help: "write the replacements back to the files instead of printing",
+    },
+    Opt {
+        short: None,
+        long: "backup",
+        value: Some("SUFFIX"),
+        help: "with --in-place, keep the original of each file as FILE_PATH.SUFFIX",
+    },
+    Opt {
+        short: Some('a'),
+        long: "text",
+        value: None,
+        help: "search binary files as if they were text",
+    },
+    Opt {
+        short: None,
+        long: "binary-files",
+        value: Some("TYPE"),
+        help: "what to do with binary files: binary, text or without-match",
+    },
+    Opt {
+        short: None,
+        long: "encoding",
+        value: Some("ENC"),
+        help: "read files as utf-8, utf-16le, utf-16be, latin1 or windows-1252",
+    },
+    Opt {
+        short: Some('n'),
+        long: "line-number",
+        value: None,
+        help: "print the line number of each line",
+    },
+    Opt {
+        short: None,
+        long: "column",
+        value: None,
+        help: "print the column of the first match in each line",
+    },
+    Opt {
+        short: Some('b'),
+        long: "byte-offset",
+        value: None,
+        help: "print the byte offset of each line",
+    },
+    Opt {
+        short: Some('A'),
+        long: "after-context",
+        value: Some("NUM"),
+        help: "print NUM lines after each match",
+    },
+    Opt {
+        short: Some('B'),
+        long: "before-context",
+        value: Some("NUM"),
+        help: "print NUM lines before each match",
+    },
+    Opt {
+        short: Some('C'),
+        long: "context",
+        value: Some("NUM"),
+        help: "print NUM lines before and after each match",
+    },
+    Opt {
+        short: Some('H'),
+        long: "with-filename",
+        value: None,
+        help: "print the file name for each line",
+    },
+    Opt {
+        short: Some('h'),
+        long: "no-filename",
+        value: None,
+        help: "never print file names",
+    },
+    Opt {
+        short: None,
+        long: "label",
+        value: Some("LABEL"),
+        help: "the name to print for standard input",
+    },
+    Opt {
+        short: None,
+        long: "color",
+        value: Some("WHEN"),
+        help: "highlight matches: auto, always or never (default: auto)",
+    },
+    Opt {
+        short: None,
+        long: "json",
+        value: None,
+        help: "print the results as JSON Lines, one event per line",
+    },
+    Opt {
+        short: None,
+        long: "max-depth",
+        value: Some("NUM"),
+        help: "don't descend more than NUM directories deep",
+    },
+    Opt {
+        short: None,
+        long: "follow",
+        value: None,
+        help: "follow symbolic links when searching directories",
+    },
+    Opt {
+        short: None,
+        long: "hidden",
+        value: None,
+        help: "search hidden files and directories",
+    },
+    Opt {
+        short: None,
+        long: "no-ignore",
+        value: None,
+        help: "search files listed in .gitignore and .minigrepignore files",
+    },
+    Opt {
+        short: None,
+        long: "include",
+        value: Some("GLOB"),
+        help: "only search files whose names match GLOB",
+    },
+    Opt {
+        short: None,
+        long: "exclude",
+        value: Some("GLOB"),
+        help: "skip files whose names match GLOB",
+    },
+    Opt {
+        short: None,
+        long: "exclude-dir",
+        value: Some("GLOB"),
+        help: "skip directories whose names match GLOB",
+    },
+    Opt {
+        short: Some('t'),
+        long: "type",
+        value: Some("TYPE"),
+        help: "only search files of TYPE, like rust or py (see below)",
+    },
+    Opt {
+        short: None,
+        long: "type-add",
+        value: Some("TYPE:GLOB"),
+        help: "add GLOB, or several separated by ',', to the file type TYPE",
+    },
+    Opt {
+        short: Some('j'),
+        long: "threads",
+        value: Some("NUM"),
+        help: "search NUM files at once (default: one per CPU)",
+    },
+    Opt {
+        short: None,
+        long: "unordered",
+        value: None,
+        help: "print results as soon as they are found, in any file order",
+    },
+    Opt {
+        short: None,
+        long: "no-config",
+        value: None,
+        help: "ignore the config file and MINIGREP_OPTS",
+    },
+    Opt {
+        short: None,
+        long: "help",
+        value: None,
+        help: "print this help and exit",
+    },
+    Opt {
+        short: Some('V'),
+        long: "version",
+        value: None,
+        help: "print the version and exit",
+    },
+];
+
+// Options that only make sense for a single search, and so can't be given
+// ahead of time in the config file or MINIGREP_OPTS. A pattern given there
+// would also turn the query on the command line into a file path.
+const NOT_DEFAULTS: &[&str] = &["regexp", "file", "replace", "in-place", "help", "version"];
+
+// The options that were given, in order, with their values, and the
+// positional arguments left over.
+#[derive(Default)]
+pub(crate) struct Parsed {
+    pub options: Vec<(&'static Opt, Option<String>)>,
+    // The options turned off with --no-NAME. They have already been taken
+    // out of options, but are kept so that they can also turn off the
+    // defaults, see with_defaults.
+    pub negated: Vec<&'static Opt>,
+    pub positional: Vec<String>,
+}
+
+pub(crate) fn parse(mut args: impl Iterator<Item = String>) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            parsed.positional.extend(args);
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, attached) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let opt = match OPTIONS.iter().find(|opt| opt.long == name) {
+                Some(opt) => opt,
+                None => match negated(name) {
+                    Some(_) if attached.is_some() => {
+                        return Err(format!("option '--{name}' doesn't take a value"));
+                    }
+                    Some(opt) => {
+                        parsed.options.retain(|(given, _)| given.long != opt.long);
+                        parsed.negated.push(opt);
+                        continue;
+                    }
+                    None => return Err(format!("unknown option '--{name}'")),
+                },
+            };
+            let value = match (opt.value, attached) {
+                (None, Some(_)) => {
+                    return Err(format!("option '--{name}' doesn't take a value"));
+                }
+                (None, None) => None,
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => Some(next_value(&mut args, opt)?),
+            };
+            parsed.options.push((opt, value));
+            continue;
+        }
+
+        // A lone "-" is positional; it is the conventional name for stdin.
+        let shorts = match arg.strip_prefix('-') {
+            Some(shorts) if !shorts.is_empty() => shorts,
+            _ => {
+                parsed.positional.push(arg);
+                continue;
+            }
+        };
+        for (i, c) in shorts.char_indices() {
+            let opt = match OPTIONS.iter().find(|opt| opt.short == Some(c)) {
+                Some(opt) => opt,
+                None => return Err(format!("unknown option '-{c}'")),
+            };
+            if opt.value.is_none() {
+                parsed.options.push((opt, None));
+                continue;
+            }
+            // An option with a value takes the rest of the argument,
+            // or the next argument if there is nothing left.
+            let rest = &shorts[i + c.len_utf8()..];
+            let value = if rest.is_empty() {
+                next_value(&mut args, opt)?
+            } else {
+                rest.to_string()
+            };
+            parsed.options.push((opt, Some(value)));
+            break;
+        }
+    }
+
+    Ok(parsed)
+}
+
+// The option that --NAME turns off, if NAME is no- followed by an option
+// without a value. Options that already start with no- can't be turned
+// off again.
+fn negated(name: &str) -> Option<&'static Opt> {
+    let name = name.strip_prefix("no-")?;
+    OPTIONS
+        .iter()
+        .find(|opt| opt.long == name && opt.value.is_none() && !name.starts_with("no-"))
+}
+
+// Puts the options from the config file and MINIGREP_OPTS in front of
+// those given on the command line. Since the last of an option wins, the
+// command line overrides MINIGREP_OPTS, which overrides the config file,
+// and an on/off option set in either can be turned off again with --no-NAME.
+//
+// The config file is the one named by MINIGREP_CONFIG_PATH, or else
+// $XDG_CONFIG_HOME/minigrep/config, with XDG_CONFIG_HOME defaulting to
+// ~/.config. Blank lines and lines starting with '#' are skipped, and the
+// rest are split into arguments the same way as MINIGREP_OPTS:
+//
+//   # Always show line numbers, and search web files with -t web.
+//   --line-number
+//   --type-add 'web:*.html,*.css'
+//
+// --no-config in MINIGREP_OPTS leaves out the config file, as it does on
+// the command line, but in the config file itself it makes no sense.
+pub(crate) fn with_defaults(parsed: Parsed) -> Result<Parsed, MinigrepError> {
+    let env_options = match env::var_os("MINIGREP_OPTS") {
+        Some(opts) => {
+            let opts = opts
+                .into_string()
+                .map_err(|_| String::from("invalid value for MINIGREP_OPTS: not valid unicode"))?;
+            let args = split_args(&opts).map_err(|e| format!("MINIGREP_OPTS: {e}"))?;
+            parse_defaults(args, "MINIGREP_OPTS")?
+        }
+        None => Parsed::default(),
+    };
+    let no_config = env_options
+        .options
+        .iter()
+        .any(|(opt, _)| opt.long == "no-config");
+
+    let file_options = match no_config {
+        true => None,
+        false => config_path(),
+    };
+    let file_options = match file_options {
+        Some((path, required)) => read_config(&path, required)?,
+        None => None,
+    };
+    Ok(layer(file_options.unwrap_or_default(), env_options, parsed))
+}
+
+// Puts each set of options in front of the next, with the options each
+// one turns off with --no-NAME taken out of those before it.
+fn layer(config: Parsed, env: Parsed, command_line: Parsed) -> Parsed {
+    let mut layered = Parsed::default();
+    for parsed in [config, env, command_line] {
+        layered
+            .options
+            .retain(|(opt, _)| !parsed.negated.iter().any(|n| n.long == opt.long));
+        layered.options.extend(parsed.options);
+        layered.negated.extend(parsed.negated);
+        layered.positional = parsed.positional;
+    }
+    layered
+}
+
+// Parses default options from source, which can't include a query, file
+// paths or any of NOT_DEFAULTS.
+fn parse_defaults(args: Vec<String>, source: &str) -> Result<Parsed, String> {
+    let parsed = parse(args.into_iter()).map_err(|e| format!("{source}: {e}"))?;
+    if let Some(arg) = parsed.positional.first() {
+        return Err(format!(
+            "{source}: only options can be given here, not '{arg}'"
+        ));
+    }
+    match parsed
+        .options
+        .iter()
+        .find(|(opt, _)| NOT_DEFAULTS.contains(&opt.long))
+    {
+        Some((opt, _)) => Err(format!("{source}: --{} can't be given here", opt.long)),
+        None => Ok(parsed),
+    }
+}
+
+// Where the config file is, and whether it has to be there. A path given
+// in MINIGREP_CONFIG_PATH has to be readable, but the usual location is
+// only read if there is a file there.
+fn config_path() -> Option<(PathBuf, bool)> {
+    let non_empty = |name| env::var_os(name).filter(|value| !value.is_empty());
+    match (
+        non_empty("MINIGREP_CONFIG_PATH"),
+        non_empty("XDG_CONFIG_HOME"),
+    ) {
+        (Some(path), _) => Some((PathBuf::from(path), true)),
+        (None, Some(dir)) => Some((Path::new(&dir).join("minigrep/config"), false)),
+        (None, None) => {
+            let home = non_empty("HOME")?;
+            Some((Path::new(&home).join(".config/minigrep/config"), false))
+        }
+    }
+}
+
+// Reads and parses the config file at path, if there is one.
+fn read_config(path: &Path, required: bool) -> Result<Option<Parsed>, MinigrepError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(e) => return Err(MinigrepError::reading_text(path, e)),
+    };
+    let path = path.display();
+    let mut args = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if !line.is_empty() && !line.starts_with('#') {
+            args.extend(split_args(line).map_err(|e| format!("{path}: {e}"))?);
+        }
+    }
+    let parsed = parse_defaults(args, &path.to_string())?;
+    if parsed
+        .options
+        .iter()
+        .any(|(opt, _)| opt.long == "no-config")
+    {
+        return Err(format!("{path}: --no-config can't be used in the config file").into());
+    }
+    Ok(Some(parsed))
+}
+
+// Splits text into arguments at whitespace, a bit like a shell would.
+// Quotes keep an argument with spaces in it together: anything goes
+// inside single quotes, and inside double quotes a '\' escapes a '"' or
+// another '\'. Outside quotes, a '\' escapes any character.
+pub(crate) fn split_args(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // Whether there is an argument in progress, which might be an empty
+    // one given as '' or "".
+    let mut in_arg = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+                continue;
+            }
+            '\'' => loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => arg.push(c),
+                    None => return Err(String::from("missing closing quote")),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\')) => arg.push(c),
+                        Some(c) => {
+                            arg.push('\\');
+                            arg.push(c);
+                        }
+                        None => return Err(String::from("missing closing quote")),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err(String::from("missing closing quote")),
+                }
+            },
+            '\\' => arg.push(chars.next().unwrap_or('\\')),
+            c => arg.push(c),
+        }
+        in_arg = true;
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, opt: &Opt) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("option '--{}' needs a value", opt.long))
+}
+
+// Parses the value of an option that takes a number.
+pub(crate) fn number(opt: &Opt, value: Option<String>) -> Result<usize, String> {
+    let value = value.unwrap_or_default();
+    value.parse().map_err(|_| {
+        format!(
+            "invalid value '{value}' for '--{}': expected a whole number",
+            opt.long
+        )
+    })
+}
+
+pub(crate) enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+// Parses the value of --color.
+pub(crate) fn color(opt: &Opt, value: Option<String>) -> Result<Color, String> {
+    let value = value.unwrap_or_default();
+    match value.as_str() {
+        "auto" => Ok(Color::Auto),
+        "always" => Ok(Color::Always),
+        "never" => Ok(Color::Never),
+        _ => Err(format!(
+            "invalid value '{value}' for '--{}': expected auto, always or never",
+            opt.long
+        )),
+    }
+}
+
+// Parses the value of --binary-files.
+pub(crate) fn binary_files(opt: &Opt, value: Option<String>) -> Result<BinaryFiles, String> {
+    let value = value.unwrap_or_default();
+    match value.as_str() {
+        "binary" => Ok(BinaryFiles::Binary),
+        "text" => Ok(BinaryFiles::Text),
+        "without-match" => Ok(BinaryFiles::WithoutMatch),
+        _ => Err(format!(
+            "invalid value '{value}' for '--{}': expected binary, text or without-match",
+            opt.long
+        )),
+    }
+}
+
+// Parses the value of --encoding, where "auto" means the default of UTF-8
+// unless a file has a byte order mark.
+pub(crate) fn encoding(opt: &Opt, value: Option<String>) -> Result<Option<Encoding>, String> {
+    let value = value.unwrap_or_default();
+    if value == "auto" {
+        return Ok(None);
+    }
+    match Encoding::from_label(&value) {
+        Some(encoding) => Ok(Some(encoding)),
+        None => Err(format!(
+            "invalid value '{value}' for '--{}': expected auto, utf-8, utf-16le, utf-16be, latin1 or windows-1252",
+            opt.long
+        )),
+    }
+}
+
+// Whether an environment variable is set to anything but an empty string.
+// Some variables, like NO_COLOR, mean the same whatever they are set to.
+pub(crate) fn env_set(name: &str) -> bool {
+    env::var_os(name).is_some_and(|value| !value.is_empty())
+}
+
+// Reads an on/off setting from an environment variable. Unset means off,
+// but when it is set the value has to be recognisable, so that
+// IGNORE_CASE=0 turns the setting off rather than on.
+pub(crate) fn env_bool(name: &str) -> Result<bool, String> {
+    let value = match env::var(name) {
+        Ok(value) => value,
+        Err(env::VarError::NotPresent) => return Ok(false),
+        Err(env::VarError::NotUnicode(_)) => {
+            return Err(format!("invalid value for {name}: not valid unicode"));
+        }
+    };
+    parse_bool(&value).ok_or_else(|| {
+        format!(
+            "invalid value '{value}' for {name}: expected 1, 0, true, false, yes, no, on or off"
+        )
+    })
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "" | "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+pub(crate) fn usage() -> String {
+    let mut usage = String::from(
+        "Usage: minigrep [OPTIONS] QUERY [FILE_PATH]...\n       \
+         minigrep [OPTIONS] -e PATTERN... [FILE_PATH]...\n\
+         \n\
+         Searches each FILE_PATH for lines containing QUERY. A FILE_PATH can\n\
+         be a glob like 'src/**/*.rs', and if it is a directory, every file\n\
+         below it is searched. With no FILE_PATH, or when FILE_PATH is -,\n\
+         standard input is searched.\n\
+         \n\
+         Options:\n",
+    );
+
+    let names: Vec<String> = OPTIONS
+        .iter()
+        .map(|opt| {
+            let short = match opt.short {
+                Some(c) => format!("-{c}, "),
+                None => String::from("    "),
+            };
+            let value = opt.value.map(|v| format!(" {v}")).unwrap_or_default();
+            format!("{short}--{}{value}", opt.long)
+        })
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    for (name, opt) in names.iter().zip(OPTIONS) {
+        usage.push_str(&format!("  {name:width$}  {}\n", opt.help));
+    }
+    usage.push_str(
+        "\nAn option without a value can be turned off again with --no-NAME,\n\
+         like --no-json, which undoes it in the config file or MINIGREP_OPTS.\n",
+    );
+
+    let types = Types::default();
+    let types: Vec<&str> = types.names().collect();
+    usage.push_str(&format!("\nFile types for -t:\n  {}\n", types.join(", ")));
+
+    usage.push_str(
+        "\n\
+         Environment:\n  \
+         IGNORE_CASE           set to 1 to match without regard to case by default\n  \
+         SMART_CASE            set to 1 to use --smart-case by default\n  \
+         NO_COLOR              set to anything to turn off colors unless --color=always\n  \
+         MINIGREP_OPTS         options to use ahead of those on the command line\n  \
+         MINIGREP_CONFIG_PATH  a file of options to use ahead of MINIGREP_OPTS,\n                        \
+         instead of $XDG_CONFIG_HOME/minigrep/config\n\
+         \n\
+         Exit status:\n  \
+         0 if a line matched, 1 if none did, and 2 if there was an error\n",
+    );
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn names(parsed: &Parsed) -> Vec<(&str, Option<&str>)> {
+        parsed
+            .options
+            .iter()
+            .map(|(opt, value)| (opt.long, value.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn short_and_long_options() {
+        let parsed = parse(args(&["-in", "query", "--context=2", "-A", "3", "file"])).unwrap();
+        assert_eq!(
+            vec![
+                ("ignore-case", None),
+                ("line-number", None),
+                ("context", Some("2")),
+                ("after-context", Some("3")),
+            ],
+            names(&parsed)
+        );
+        assert_eq!(vec!["query", "file"], parsed.positional);
+    }
+
+    #[test]
+    fn attached_short_values() {
+        let parsed = parse(args(&["-nB2", "-C", "1"])).unwrap();
+        assert_eq!(
+            vec![
+                ("line-number", None),
+                ("before-context", Some("2")),
+                ("context", Some("1")),
+            ],
+            names(&parsed)
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let parsed = parse(args(&["-i", "--", "-n", "-"])).unwrap();
+        assert_eq!(vec![("ignore-case", None)], names(&parsed));
+        assert_eq!(vec!["-n", "-"], parsed.positional);
+    }
+
+    #[test]
+    fn usage_errors() {
+        let error = |list: &[&str]| parse(args(list)).err().unwrap();
+        assert_eq!("unknown option '--frobnicate'", error(&["--frobnicate"]));
+        assert_eq!("unknown option '-z'", error(&["-iz"]));
+        assert_eq!("option '--context' needs a value", error(&["-C"]));
+        assert_eq!(
+            "option '--column' doesn't take a value",
+            error(&["--column=3"])
+        );
+    }
+
+    #[test]
+    fn strict_booleans() {
+        assert_eq!(Some(true), parse_bool("1"));
+        assert_eq!(Some(true), parse_bool("Yes"));
+        assert_eq!(Some(false), parse_bool("0"));
+        assert_eq!(Some(false), parse_bool("off"));
+        assert_eq!(None, parse_bool("maybe"));
+    }
+
+    #[test]
+    fn splits_arguments() {
+        assert_eq!(
+            vec!["-n", "--type-add", "web:*.html two", "a\"b", "", "c d"],
+            split_args(r#" -n --type-add 'web:*.html two'  "a\"b" '' c\ d "#).unwrap()
+        );
+        assert_eq!(
+            Err(String::from("missing closing quote")),
+            split_args("--label 'oops")
+        );
+    }
+
+    #[test]
+    fn defaults_cant_be_positional() {
+        let error = parse_defaults(vec![String::from("query")], "MINIGREP_OPTS").err();
+        assert_eq!(
+            Some(String::from(
+                "MINIGREP_OPTS: only options can be given here, not 'query'"
+            )),
+            error
+        );
+    }
+
+    #[test]
+    fn no_turns_options_off() {
+        let parsed = parse(args(&["-n", "--json", "--no-line-number", "-c"])).unwrap();
+        assert_eq!(vec![("json", None), ("count", None)], names(&parsed));
+        assert_eq!("line-number", parsed.negated[0].long);
+
+        let error = |list: &[&str]| parse(args(list)).err().unwrap();
+        assert_eq!(
+            "option '--no-json' doesn't take a value",
+            error(&["--no-json=1"])
+        );
+        // Only options without a value can be turned off.
+        assert_eq!("unknown option '--no-context'", error(&["--no-context"]));
+        assert_eq!(
+            "unknown option '--no-no-ignore'",
+            error(&["--no-no-ignore"])
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_env_overrides_config() {
+        let defaults = |list: &[&str]| parse_defaults(args(list).collect(), "test").unwrap();
+        let config = defaults(&["-n", "--json", "-A", "1", "--hidden"]);
+        let env = defaults(&["-A", "2", "--no-line-number"]);
+        let command_line = parse(args(&["--no-json", "-c", "-A", "3", "query"])).unwrap();
+
+        let layered = layer(config, env, command_line);
+        assert_eq!(
+            vec![
+                ("after-context", Some("1")),
+                ("hidden", None),
+                ("after-context", Some("2")),
+                ("count", None),
+                ("after-context", Some("3")),
+            ],
+            names(&layered)
+        );
+        assert_eq!(vec!["query"], layered.positional);
+    }
+
+    #[test]
+    fn defaults_cant_be_for_one_search() {
+        for (list, name) in [
+            (&["-e", "beta"][..], "regexp"),
+            (&["-f", "patterns.txt"], "file"),
+            (&["--replace", "x"], "replace"),
+            (&["--help"], "help"),
+            (&["-nV"], "version"),
+        ] {
+            let error = parse_defaults(args(list).collect(), "MINIGREP_OPTS").err();
+            assert_eq!(
+                Some(format!("MINIGREP_OPTS: --{name} can't be given here")),
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn reads_the_config_file() {
+        let dir = crate::TempDir::new("config-file");
+        let path = dir.join("config");
+        fs::write(
+            &path,
+            "# Line numbers everywhere.\n  -n\n\n--type-add 'web:*.html'\n",
+        )
+        .unwrap();
+        let parsed = read_config(&path, true).unwrap().unwrap();
+        assert_eq!(
+            vec![("line-number", None), ("type-add", Some("web:*.html"))],
+            names(&parsed)
+        );
+
+        // The usual location doesn't have to exist, but a path that was
+        // asked for does.
+        let missing = dir.join("missing");
+        assert!(read_config(&missing, false).unwrap().is_none());
+        let error = read_config(&missing, true).err();
+        assert!(matches!(error, Some(MinigrepError::Io { path, .. }) if path == missing));
+
+        fs::write(&path, "--no-config\n").unwrap();
+        let error = read_config(&path, true).err().unwrap();
+        assert_eq!(
+            format!(
+                "{}: --no-config can't be used in the config file",
+                path.display()
+            ),
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn usage_lists_every_option() {
+        let usage = usage();
+        for opt in OPTIONS {
+            assert!(usage.contains(&format!("--{}", opt.long)));
+        }
+    }
+}
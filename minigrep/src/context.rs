@@ -0,0 +1,34 @@
+use crate::Match;
+
+// A line to be printed, either one that matched or one of the lines
+// of context around a match. Searcher::search_reader works out which
+// lines of context are wanted as it reads, and passes on each one once.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line<'a> {
+    Match(Match<'a>),
+    Context {
+        line: &'a str,
+        line_number: usize,
+        byte_offset: usize,
+        // The bytes that were read, if they weren't valid UTF-8, as with
+        // Match::raw.
+        raw: Option<&'a [u8]>,
+    },
+}
+
+impl<'a> Line<'a> {
+    pub fn line_number(&self) -> usize {
+        match self {
+            Line::Match(m) => m.line_number,
+            Line::Context { line_number, .. } => *line_number,
+        }
+    }
+
+    // The line as it was read.
+    pub fn bytes(&self) -> &'a [u8] {
+        match self {
+            Line::Match(m) => m.bytes(),
+            Line::Context { line, raw, .. } => raw.unwrap_or(line.as_bytes()),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+// Reading text that isn't UTF-8.
+//
+// Everything else in minigrep works on UTF-8, so text in other encodings
+// is converted to UTF-8 as it is read. A file that starts with a byte
+// order mark (BOM) says what its encoding is: EF BB BF for UTF-8, FF FE
+// for UTF-16 little-endian and FE FF for UTF-16 big-endian. Otherwise the
+// encoding has to be given with --encoding, or UTF-8 is assumed.
+//
+// After converting, line numbers are the same as in the original, but
+// byte offsets count the bytes of the UTF-8 text rather than of the file.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // ISO-8859-1, where each byte is the Unicode character with the same
+    // number.
+    Latin1,
+    // Like Latin-1, except that 0x80 to 0x9F are curly quotes, the euro
+    // sign and so on rather than control characters. Most text that
+    // claims to be Latin-1 is really this.
+    Windows1252,
+}
+
+impl Encoding {
+    // Looks up an encoding by one of its usual names, ignoring case.
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        let encoding = match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8,
+            "utf-16le" | "utf16le" => Encoding::Utf16Le,
+            "utf-16be" | "utf16be" => Encoding::Utf16Be,
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Encoding::Latin1,
+            "windows-1252" | "cp1252" => Encoding::Windows1252,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+            Encoding::Latin1 | Encoding::Windows1252 => b"",
+        }
+    }
+}
+
+// Wraps reader so that what comes out of it is UTF-8. The encoding is
+// taken from a BOM if there is one and no encoding is given, and a BOM
+// for the encoding being used is skipped over rather than being searched.
+pub fn decode<R: BufRead>(
+    mut reader: R,
+    encoding: Option<Encoding>,
+) -> io::Result<DecodeReader<R>> {
+    let start = reader.fill_buf()?;
+    let sniffed = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|encoding| start.starts_with(encoding.bom()));
+    let encoding = encoding.or(sniffed).unwrap_or(Encoding::Utf8);
+    if sniffed == Some(encoding) {
+        reader.consume(encoding.bom().len());
+    }
+    Ok(match encoding {
+        // UTF-8 is passed straight through, without any copying.
+        Encoding::Utf8 => DecodeReader::Utf8(reader),
+        _ => DecodeReader::Decoded(BufReader::new(Decoder {
+            inner: reader,
+            encoding,
+            undecoded: Vec::new(),
+            decoded: Vec::new(),
+            position: 0,
+        })),
+    })
+}
+
+// Either the original reader, or one converting it to UTF-8.
+pub enum DecodeReader<R> {
+    Utf8(R),
+    Decoded(BufReader<Decoder<R>>),
+}
+
+impl<R: BufRead> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DecodeReader::Utf8(reader) => reader.read(buf),
+            DecodeReader::Decoded(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for DecodeReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            DecodeReader::Utf8(reader) => reader.fill_buf(),
+            DecodeReader::Decoded(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            DecodeReader::Utf8(reader) => reader.consume(amount),
+            DecodeReader::Decoded(reader) => reader.consume(amount),
+        }
+    }
+}
+
+// Converts text in one of the other encodings to UTF-8, a buffer at a time.
+pub struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    // Bytes at the end of the last buffer that can't be decoded until
+    // more arrive: half of a UTF-16 code unit, or the first of a pair of
+    // surrogates that together make up one character.
+    undecoded: Vec<u8>,
+    // Decoded text waiting to be read, from position on.
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            self.decoded.clear();
+            self.position = 0;
+            let input = self.inner.fill_buf()?;
+            if input.is_empty() {
+                // Anything still waiting at the end can never be decoded.
+                if self.undecoded.is_empty() {
+                    return Ok(0);
+                }
+                self.undecoded.clear();
+                push_char(&mut self.decoded, char::REPLACEMENT_CHARACTER);
+                break;
+            }
+            let read = input.len();
+            match self.encoding {
+                Encoding::Latin1 => {
+                    for &byte in input {
+                        push_char(&mut self.decoded, byte as char);
+                    }
+                }
+                Encoding::Windows1252 => {
+                    for &byte in input {
+                        push_char(&mut self.decoded, windows_1252(byte));
+                    }
+                }
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    self.undecoded.extend_from_slice(input);
+                    let little_endian = self.encoding == Encoding::Utf16Le;
+                    let used = decode_utf16(&self.undecoded, little_endian, &mut self.decoded);
+                    self.undecoded.drain(..used);
+                }
+                Encoding::Utf8 => self.decoded.extend_from_slice(input),
+            }
+            self.inner.consume(read);
+        }
+        let available = &self.decoded[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// Decodes as much of bytes as possible as UTF-16, returning how many bytes
+// were used. A trailing odd byte, or a first surrogate that could still be
+// followed by its pair, is left for next time. Anything that isn't valid
+// becomes U+FFFD, the replacement character.
+fn decode_utf16(bytes: &[u8], little_endian: bool, out: &mut Vec<u8>) -> usize {
+    let units = bytes.chunks_exact(2).map(|pair| match little_endian {
+        true => u16::from_le_bytes([pair[0], pair[1]]),
+        false => u16::from_be_bytes([pair[0], pair[1]]),
+    });
+    let mut used = bytes.len() / 2 * 2;
+    if let Some(last) = units.clone().next_back() {
+        if (0xd800..0xdc00).contains(&last) {
+            used -= 2;
+        }
+    }
+    for c in char::decode_utf16(units.take(used / 2)) {
+        push_char(out, c.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    used
+}
+
+// The characters that Windows-1252 has in place of Latin-1's control
+// characters 0x80 to 0x9F. The five bytes it leaves undefined are kept as
+// the control characters, as web browsers do.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8], encoding: Option<Encoding>) -> String {
+        let mut text = String::new();
+        decode(bytes, encoding)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn sniffs_byte_order_marks() {
+        assert_eq!("hé\n", read(b"\xff\xfeh\0\xe9\0\n\0", None));
+        assert_eq!("hé\n", read(b"\xfe\xff\0h\0\xe9\0\n", None));
+        assert_eq!("hé\n", read(b"\xef\xbb\xbfh\xc3\xa9\n", None));
+        // The BOM isn't skipped when it doesn't match the given encoding.
+        assert_eq!("ÿþh", read(b"\xff\xfeh", Some(Encoding::Latin1)));
+    }
+
+    #[test]
+    fn single_byte_encodings() {
+        assert_eq!("café\u{80}", read(b"caf\xe9\x80", Some(Encoding::Latin1)));
+        assert_eq!(
+            "“café” €1",
+            read(b"\x93caf\xe9\x94 \x801", Some(Encoding::Windows1252))
+        );
+        assert_eq!(Some(Encoding::Windows1252), Encoding::from_label("CP1252"));
+        assert_eq!(None, Encoding::from_label("ebcdic"));
+    }
+
+    #[test]
+    fn utf16_split_across_buffers() {
+        // 😀 is a surrogate pair in UTF-16, d83d de00.
+        let bytes = b"\x3d\xd8\x00\xdea\0";
+        let mut out = Vec::new();
+        assert_eq!(0, decode_utf16(&bytes[..3], true, &mut out));
+        assert_eq!(0, decode_utf16(&bytes[..2], true, &mut out));
+        assert_eq!(6, decode_utf16(bytes, true, &mut out));
+        assert_eq!("😀a", String::from_utf8(out).unwrap());
+
+        // An odd byte at the end, or a lone surrogate, can't be decoded.
+        assert_eq!("a\u{fffd}", read(b"\xff\xfea\0b", None));
+        assert_eq!("\u{fffd}a", read(b"\xff\xfe\x00\xdca\0", None));
+    }
+}
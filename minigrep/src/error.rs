@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::regex::RegexError;
+
+// Everything that can go wrong in minigrep. Keeping them in one enum,
+// rather than boxing whatever error comes along, lets main tell a mistake
+// on the command line apart from a file that couldn't be read, and lets
+// library users match on the kind of problem.
+#[derive(Debug)]
+pub enum MinigrepError {
+    // The command line, config file or MINIGREP_OPTS didn't make sense.
+    BadArguments(String),
+    // Reading or writing a file failed. io::Error doesn't say which file
+    // it was about, so the path is kept alongside it.
+    Io { path: PathBuf, source: io::Error },
+    // A pattern isn't a valid regular expression.
+    InvalidPattern(RegexError),
+    // A file had to be UTF-8, like a pattern file or a file being edited
+    // in place, but wasn't.
+    Encoding { path: PathBuf, source: io::Error },
+    // Some of the files couldn't be searched. Each one has already been
+    // reported as it happened, so only the total is shown, but what went
+    // wrong with each is kept, in the order they were reported.
+    Incomplete { failed: Vec<MinigrepError> },
+}
+
+impl MinigrepError {
+    // Wraps an I/O error from reading a file that has to be UTF-8 text.
+    // Reading text that isn't UTF-8 into a String fails with InvalidData,
+    // which is an encoding problem rather than the file being unreadable.
+    // Only use this where a String is being read, since elsewhere
+    // InvalidData can mean other things.
+    pub fn reading_text(path: impl Into<PathBuf>, source: io::Error) -> MinigrepError {
+        let path = path.into();
+        match source.kind() {
+            io::ErrorKind::InvalidData => MinigrepError::Encoding { path, source },
+            _ => MinigrepError::Io { path, source },
+        }
+    }
+}
+
+impl fmt::Display for MinigrepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MinigrepError::BadArguments(message) => write!(f, "{message}"),
+            MinigrepError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            MinigrepError::InvalidPattern(e) => write!(f, "{e}"),
+            MinigrepError::Encoding { path, .. } => {
+                write!(f, "{}: not valid UTF-8", path.display())
+            }
+            MinigrepError::Incomplete { failed } => {
+                write!(f, "{} of the files could not be searched", failed.len())
+            }
+        }
+    }
+}
+
+impl Error for MinigrepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MinigrepError::Io { source, .. } | MinigrepError::Encoding { source, .. } => {
+                Some(source)
+            }
+            MinigrepError::InvalidPattern(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// The argument parsing functions report problems as plain messages, so
+// they can be used with ? in Config::build. Problems with files should be
+// wrapped with their path instead.
+impl From<String> for MinigrepError {
+    fn from(message: String) -> MinigrepError {
+        MinigrepError::BadArguments(message)
+    }
+}
+
+impl From<RegexError> for MinigrepError {
+    fn from(e: RegexError) -> MinigrepError {
+        MinigrepError::InvalidPattern(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_keep_their_path() {
+        let e = MinigrepError::reading_text("notes.txt", io::ErrorKind::NotFound.into());
+        assert!(matches!(e, MinigrepError::Io { .. }));
+        assert!(e.to_string().starts_with("notes.txt: "));
+        assert!(e.source().is_some());
+
+        let e = MinigrepError::reading_text("latin1.txt", io::ErrorKind::InvalidData.into());
+        assert_eq!("latin1.txt: not valid UTF-8", e.to_string());
+        assert!(e.source().is_some());
+    }
+}
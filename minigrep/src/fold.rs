@@ -0,0 +1,103 @@
+// Case folding, for matching text without regard to case.
+//
+// Lowercasing isn't quite enough for this. Some characters have more than
+// one lowercase form, like the Greek sigma, which is written 'ς' at the end
+// of a word and 'σ' elsewhere, and some only match a longer string in the
+// other case, like the German 'ß', whose uppercase form is "SS". Going to
+// lowercase, then uppercase, then back to lowercase irons these out: 'ς',
+// 'σ' and 'Σ' all fold to 'σ', and 'ẞ', 'ß' and "SS" all fold to "ss".
+//
+// Matching works on the folded form of the text one character at a time,
+// so no folded copy of each line is ever built. A match always covers
+// whole characters of the original text: "ss" matches 'ß', but "s" on its
+// own doesn't match half of one.
+
+// The folded form of c, which is usually one character but can be more.
+pub fn fold(c: char) -> impl Iterator<Item = char> {
+    c.to_lowercase()
+        .flat_map(char::to_uppercase)
+        .flat_map(char::to_lowercase)
+}
+
+// Folds every character of text, for example to prepare a query.
+pub fn fold_str(text: &str) -> String {
+    text.chars().flat_map(fold).collect()
+}
+
+// Finds the non-overlapping places where text folds to the same thing as
+// query, which must already be folded. Returns byte ranges in text.
+pub fn find_all(query: &str, text: &str) -> Vec<(usize, usize)> {
+    // An empty query matches every line, at the very start.
+    if query.is_empty() {
+        return vec![(0, 0)];
+    }
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some((found, end)) = find_at(query, text, start) {
+        spans.push((found, end));
+        start = end;
+    }
+    spans
+}
+
+// Finds the first place at or after at where text folds to the same thing
+// as query, which must already be folded and not be empty.
+pub fn find_at(query: &str, text: &str, at: usize) -> Option<(usize, usize)> {
+    let mut start = at;
+    while let Some(c) = text[start..].chars().next() {
+        if let Some(end) = match_at(query, text, start) {
+            return Some((start, end));
+        }
+        start += c.len_utf8();
+    }
+    None
+}
+
+// If the text from start onwards begins with something that folds to
+// query, returns where that something ends.
+fn match_at(query: &str, text: &str, start: usize) -> Option<usize> {
+    let mut wanted = query.chars();
+    for (i, c) in text[start..].char_indices() {
+        for folded in fold(c) {
+            // Running out of query part way through a character means
+            // the match would end inside it, which doesn't count.
+            if wanted.next() != Some(folded) {
+                return None;
+            }
+        }
+        if wanted.as_str().is_empty() {
+            return Some(start + i + c.len_utf8());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_special_cases() {
+        assert_eq!("ss", fold_str("ß"));
+        assert_eq!("ss", fold_str("ẞ"));
+        assert_eq!("σσσ", fold_str("ςσΣ"));
+        assert_eq!("i\u{307}", fold_str("İ"));
+        assert_eq!("k", fold_str("\u{212A}"));
+    }
+
+    #[test]
+    fn spans_cover_whole_characters() {
+        assert_eq!(
+            vec![(7, 14)],
+            find_all(&fold_str("STRASSE"), "große straße")
+        );
+        assert_eq!(vec![(0, 7)], find_all(&fold_str("strasse"), "straße"));
+        assert!(find_all("s", "ß").is_empty());
+        // A final sigma matches a sigma anywhere else.
+        assert_eq!(vec![(0, 8)], find_all(&fold_str("ΟΔΟΣ"), "οδος"));
+        assert_eq!(
+            vec![(0, 8), (9, 17)],
+            find_all(&fold_str("οδος"), "ΟΔΟΣ ΟΔΟΣ")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::fold::fold;
+
+// Approximate matching for --fuzzy: finding the part of a line that is
+// closest to the query, allowing up to a given number of edits. The
+// Levenshtein distance between two strings is the fewest single character
+// insertions, deletions or substitutions that turn one into the other, so
+// "hostnme" and "hotsname" are both within 2 of "hostname".
+//
+// This uses the usual dynamic programming table, one column per character
+// of the line, where row i of a column is the distance between the first
+// i characters of the query and the best substring of the line ending
+// there. Row 0 is always 0, since a match can start anywhere.
+//
+// Rows further down a column can only be as small as rows above them in
+// the previous column, so once a row goes over the limit every row below
+// it is over too. Only the rows up to the last one within the limit are
+// worked out (Ukkonen's cut-off), which makes each column cost about as
+// much as the number of edits allowed rather than the length of the query.
+// A line is searched in time proportional to its length times the edits.
+
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+    query: Vec<char>,
+    max_edits: usize,
+    ignore_case: bool,
+}
+
+// The best match in a line: its byte range and how many edits away from
+// the query it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FuzzyMatch {
+    pub start: usize,
+    pub end: usize,
+    pub distance: usize,
+}
+
+impl Fuzzy {
+    pub fn new(query: &str, max_edits: usize, ignore_case: bool) -> Fuzzy {
+        Fuzzy {
+            query: query.chars().collect(),
+            max_edits,
+            ignore_case,
+        }
+    }
+
+    // Finds the substring of line with the smallest distance from the
+    // query, if any is within max_edits. Of several equally close ones,
+    // the one that ends first wins, and of those the shortest.
+    pub fn find(&self, line: &str) -> Option<FuzzyMatch> {
+        let m = self.query.len();
+        let limit = self.max_edits;
+        // Each cell holds the distance and the byte offset in line where
+        // that best substring starts.
+        let mut column: Vec<(usize, usize)> = (0..=m).map(|i| (i, 0)).collect();
+        let mut next = column.clone();
+        // The last row that is within the limit.
+        let mut last = m.min(limit);
+
+        let mut best = (column[m].0 <= limit).then_some(FuzzyMatch {
+            start: 0,
+            end: 0,
+            distance: column[m].0,
+        });
+        for (offset, c) in line.char_indices() {
+            let end = offset + c.len_utf8();
+            next[0] = (0, end);
+            let rows = (last + 1).min(m);
+            for i in 1..=rows {
+                let cost = !self.char_eq(self.query[i - 1], c) as usize;
+                let substitute = (column[i - 1].0 + cost, column[i - 1].1);
+                let skip_query_char = (next[i - 1].0 + 1, next[i - 1].1);
+                // The row below last in the old column was over the limit,
+                // and hasn't been filled in.
+                let skip_line_char = match i <= last {
+                    true => (column[i].0 + 1, column[i].1),
+                    false => (usize::MAX, 0),
+                };
+                // On a tie, the later start gives the shorter match.
+                next[i] = [substitute, skip_query_char, skip_line_char]
+                    .into_iter()
+                    .min_by_key(|&(distance, start)| (distance, usize::MAX - start))
+                    .unwrap_or(substitute);
+            }
+            last = rows;
+            while last > 0 && next[last].0 > limit {
+                last -= 1;
+            }
+            std::mem::swap(&mut column, &mut next);
+
+            if last == m && best.is_none_or(|best| column[m].0 < best.distance) {
+                best = Some(FuzzyMatch {
+                    start: column[m].1,
+                    end,
+                    distance: column[m].0,
+                });
+                if column[m].0 == 0 {
+                    break;
+                }
+            }
+        }
+        best
+    }
+
+    fn char_eq(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && fold(a).eq(fold(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(query: &str, max_edits: usize, line: &'a str) -> Option<(&'a str, usize)> {
+        let m = Fuzzy::new(query, max_edits, false).find(line)?;
+        Some((&line[m.start..m.end], m.distance))
+    }
+
+    #[test]
+    fn finds_closest_substring() {
+        assert_eq!(
+            Some(("hostname", 0)),
+            find("hostname", 2, "the hostname is")
+        );
+        assert_eq!(Some(("hostnme", 1)), find("hostname", 2, "the hostnme is"));
+        assert_eq!(Some(("hotsname", 2)), find("hostname", 2, "see hotsname."));
+        assert_eq!(None, find("hostname", 1, "see hotsname."));
+        assert_eq!(None, find("hostname", 2, "nothing to see"));
+        // An exact match later on beats a close one earlier.
+        assert_eq!(Some(("db01", 0)), find("db01", 1, "db0 then db01"));
+    }
+
+    #[test]
+    fn unicode_and_case() {
+        assert_eq!(Some(("Zürich", 1)), find("Zurich", 1, "in Zürich"));
+        let m = Fuzzy::new("ZURICH", 0, true).find("in zurich").unwrap();
+        assert_eq!((3, 9, 0), (m.start, m.end, m.distance));
+    }
+
+    #[test]
+    fn long_lines_stay_within_the_limit() {
+        let line = "x".repeat(100_000) + "needle";
+        let m = Fuzzy::new("neeedle", 1, false).find(&line).unwrap();
+        assert_eq!((100_000, 100_006, 1), (m.start, m.end, m.distance));
+    }
+}
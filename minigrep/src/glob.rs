@@ -0,0 +1,296 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Shell-style wildcard patterns, so that minigrep can expand paths like
+// `src/**/*.rs` itself on shells (or scripts) that don't do it for us.
+//
+//   *       any run of characters, except '/'
+//   ?       any single character, except '/'
+//   [abc]   one of the characters listed, with ranges like [a-z]
+//   [!abc]  any character not listed; [^abc] works too
+//   **      as a whole path component, any number of directories
+//   \*      a literal '*', and likewise for the other special characters
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+// A wildcard pattern for a single file name, or a path when the pattern
+// contains '/'. Wildcards never match across a '/'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' => {
+                    // Runs of stars mean the same as one inside a name.
+                    while chars.get(i + 1) == Some(&'*') {
+                        i += 1;
+                    }
+                    Token::AnyRun
+                }
+                '?' => Token::AnyChar,
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Char(chars[i])
+                }
+                '[' => match parse_class(&chars, i + 1) {
+                    Some((token, end)) => {
+                        i = end;
+                        token
+                    }
+                    // An unclosed bracket is just a bracket.
+                    None => Token::Char('['),
+                },
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+        Pattern { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        // The usual wildcard algorithm: match greedily and, on a mismatch,
+        // go back to the most recent '*' and let it swallow one more
+        // character. Only the most recent star needs remembering, since
+        // an earlier one could only lead to the same positions.
+        let (mut t, mut p) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while t < text.len() {
+            let step = match self.tokens.get(p) {
+                Some(Token::AnyRun) => {
+                    star = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(Token::Char(c)) => *c == text[t],
+                Some(Token::AnyChar) => text[t] != '/',
+                Some(Token::Class { ranges, negated }) => {
+                    let found = ranges
+                        .iter()
+                        .any(|&(lo, hi)| lo <= text[t] && text[t] <= hi);
+                    text[t] != '/' && found != *negated
+                }
+                None => false,
+            };
+            if step {
+                t += 1;
+                p += 1;
+                continue;
+            }
+            match star {
+                Some((star_p, star_t)) if text[star_t] != '/' => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                _ => return false,
+            }
+        }
+        self.tokens[p..].iter().all(|token| *token == Token::AnyRun)
+    }
+
+    // Whether the pattern is just plain text with no wildcards.
+    pub fn is_literal(&self) -> bool {
+        self.tokens
+            .iter()
+            .all(|token| matches!(token, Token::Char(_)))
+    }
+}
+
+// Parses a bracket expression starting just after the '['.
+// Returns the token and the index of the closing ']', or None if the
+// class is never closed.
+fn parse_class(chars: &[char], mut i: usize) -> Option<(Token, usize)> {
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let start = i;
+    let mut ranges = Vec::new();
+    loop {
+        let mut c = *chars.get(i)?;
+        // A ']' straight after the opening bracket is part of the class.
+        if c == ']' && i > start {
+            return Some((Token::Class { ranges, negated }, i));
+        }
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        // A '-' between two characters makes a range, but one at the
+        // end of the class is just a '-'.
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&hi| hi != ']') {
+            i += 2;
+            let mut hi = chars[i];
+            if hi == '\\' {
+                i += 1;
+                hi = *chars.get(i)?;
+            }
+            ranges.push((c, hi));
+        } else {
+            ranges.push((c, c));
+        }
+        i += 1;
+    }
+}
+
+// Whether arg contains any of the characters that make it a glob.
+pub fn is_glob(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+// Expands a glob into the paths that match it, in sorted order.
+// The pattern is matched one path component at a time, so `src/*` finds
+// directories as well as files, and `**` matches any number of
+// directories, including none. As in a shell, wildcards only match names
+// starting with '.' if the pattern component starts with '.' too.
+pub fn expand(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let (mut candidates, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![PathBuf::from("/")], rest),
+        None => (vec![PathBuf::new()], pattern),
+    };
+
+    for component in rest.split('/').filter(|c| !c.is_empty()) {
+        let mut next = Vec::new();
+        if component == "**" {
+            for dir in candidates {
+                next.push(dir.clone());
+                descendants(&dir, &mut next)?;
+            }
+        } else {
+            let component_pattern = Pattern::new(component);
+            if component_pattern.is_literal() {
+                let name: String = component.replace('\\', "");
+                next = candidates.into_iter().map(|dir| dir.join(&name)).collect();
+            } else {
+                for dir in candidates {
+                    for name in list_dir(&dir)? {
+                        let hidden = name.starts_with('.') && !component.starts_with('.');
+                        if !hidden && component_pattern.matches(&name) {
+                            next.push(dir.join(name));
+                        }
+                    }
+                }
+            }
+        }
+        candidates = next;
+    }
+
+    let mut paths: Vec<PathBuf> = candidates
+        .into_iter()
+        .filter(|path| !path.as_os_str().is_empty() && path.exists())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+// The names of the entries in dir, or nothing if dir isn't a directory.
+fn list_dir(dir: &Path) -> io::Result<Vec<String>> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Adds every directory below dir to found, skipping hidden ones.
+fn descendants(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for name in list_dir(dir)? {
+        let path = dir.join(&name);
+        // symlink_metadata doesn't follow links, so links to directories
+        // can't send us round in circles.
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+        if is_dir && !name.starts_with('.') {
+            found.push(path.clone());
+            descendants(&path, found)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[test]
+    fn wildcards() {
+        assert!(Pattern::new("*.rs").matches("main.rs"));
+        assert!(!Pattern::new("*.rs").matches("main.rsx"));
+        assert!(!Pattern::new("*.rs").matches("src/main.rs"));
+        assert!(Pattern::new("a*b*c").matches("aXXbYYbc"));
+        assert!(Pattern::new("file?.txt").matches("file1.txt"));
+        assert!(!Pattern::new("file?.txt").matches("file10.txt"));
+        assert!(Pattern::new(r"\*.txt").matches("*.txt"));
+        assert!(!Pattern::new(r"\*.txt").matches("a.txt"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(Pattern::new("[abc].txt").matches("b.txt"));
+        assert!(!Pattern::new("[abc].txt").matches("d.txt"));
+        assert!(Pattern::new("log[0-9]").matches("log7"));
+        assert!(Pattern::new("log[!0-9]").matches("logx"));
+        assert!(!Pattern::new("log[^0-9]").matches("log7"));
+        assert!(Pattern::new("[]]").matches("]"));
+        assert!(Pattern::new("[a-]").matches("-"));
+        assert!(Pattern::new("[abc").matches("[abc"));
+    }
+
+    #[test]
+    fn expands_paths() {
+        let root = TempDir::new("glob");
+        for file in ["a.rs", "b.txt", "src/c.rs", "src/deep/d.rs", ".hidden/e.rs"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let base = root.to_string_lossy().replace('\\', "/");
+        let expand = |pattern: &str| -> Vec<String> {
+            expand(&format!("{base}/{pattern}"))
+                .unwrap()
+                .iter()
+                .map(|p| {
+                    p.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect()
+        };
+
+        assert_eq!(vec!["a.rs"], expand("*.rs"));
+        assert_eq!(vec!["a.rs", "src/c.rs", "src/deep/d.rs"], expand("**/*.rs"));
+        assert_eq!(vec!["src/c.rs", "src/deep"], expand("src/*"));
+        assert_eq!(vec![".hidden/e.rs"], expand(".*/*.rs"));
+        assert!(expand("*.md").is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::Path;
+
+use crate::glob::Pattern;
+
+// Ignore files, which list the files that a search of a directory should
+// skip, in the same format as git's .gitignore:
+//
+//   # comment     lines starting with '#' are comments, as are blank lines
+//   *.log         a pattern without a '/' matches a name at any depth
+//   /todo.txt     one with a '/' at the start or in the middle only matches
+//   doc/*.html    relative to the directory the ignore file is in
+//   build/        a '/' at the end only matches directories
+//   **/tmp        '**' as a whole component matches any number of
+//   logs/**       directories, including none
+//   !keep.log     a '!' includes again a file an earlier pattern ignored
+//   \#name        a '\' takes away the special meaning of '#' or '!'
+//
+// When several patterns match, the last one wins. Nothing can be included
+// again from inside a directory that is ignored, since the search never
+// looks inside it.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    // A '**' component.
+    AnyDirs,
+    Name(Pattern),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    parts: Vec<Part>,
+    negated: bool,
+    dir_only: bool,
+}
+
+// The rules from one ignore file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    pub fn parse(contents: &str) -> Ignore {
+        let rules = contents.lines().filter_map(parse_rule).collect();
+        Ignore { rules }
+    }
+
+    // Reads an ignore file. A file that doesn't exist or can't be read
+    // has nothing to say, so gives None, as does one without any rules.
+    pub fn from_file(path: &Path) -> Option<Ignore> {
+        let ignore = Ignore::parse(&fs::read_to_string(path).ok()?);
+        (!ignore.rules.is_empty()).then_some(ignore)
+    }
+
+    // Whether the file with the given path, relative to the directory the
+    // ignore file applies to and split into its names, is ignored (true)
+    // or included again with '!' (false). None means no pattern matched.
+    pub fn matched(&self, names: &[&str], is_dir: bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && matches(&rule.parts, names))
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Trailing spaces are dropped, unless the last one is escaped.
+    let mut line = line.trim_end_matches('\r');
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // A pattern is anchored to the ignore file's directory if it has a
+    // '/' anywhere but the end. Otherwise it can match at any depth, the
+    // same as if it started with "**/".
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    if !anchored {
+        parts.push(Part::AnyDirs);
+    }
+    for name in line.split('/') {
+        match name {
+            "**" => parts.push(Part::AnyDirs),
+            name => parts.push(Part::Name(Pattern::new(name))),
+        }
+    }
+    // "logs/**" matches everything inside logs, but not logs itself.
+    if parts.last() == Some(&Part::AnyDirs) {
+        parts.push(Part::Name(Pattern::new("*")));
+    }
+    Some(Rule {
+        parts,
+        negated,
+        dir_only,
+    })
+}
+
+fn matches(parts: &[Part], names: &[&str]) -> bool {
+    match parts.split_first() {
+        None => names.is_empty(),
+        Some((Part::AnyDirs, rest)) => (0..=names.len()).any(|skip| matches(rest, &names[skip..])),
+        Some((Part::Name(pattern), rest)) => names
+            .split_first()
+            .is_some_and(|(name, names)| pattern.matches(name) && matches(rest, names)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(ignore: &Ignore, path: &str) -> Option<bool> {
+        let (path, is_dir) = match path.strip_suffix('/') {
+            Some(dir) => (dir, true),
+            None => (path, false),
+        };
+        let names: Vec<&str> = path.split('/').collect();
+        ignore.matched(&names, is_dir)
+    }
+
+    #[test]
+    fn unanchored_and_anchored() {
+        let ignore = Ignore::parse("# build output\n\n*.log\n/todo.txt\ndoc/*.html\n");
+        assert_eq!(Some(true), ignored(&ignore, "a.log"));
+        assert_eq!(Some(true), ignored(&ignore, "src/deep/a.log"));
+        assert_eq!(Some(true), ignored(&ignore, "todo.txt"));
+        assert_eq!(None, ignored(&ignore, "src/todo.txt"));
+        assert_eq!(Some(true), ignored(&ignore, "doc/index.html"));
+        assert_eq!(None, ignored(&ignore, "src/doc/index.html"));
+        assert_eq!(None, ignored(&ignore, "doc/api/index.html"));
+    }
+
+    #[test]
+    fn directories_and_double_stars() {
+        let ignore = Ignore::parse("target/\n**/tmp/*.o\nlogs/**\n");
+        assert_eq!(Some(true), ignored(&ignore, "target/"));
+        assert_eq!(Some(true), ignored(&ignore, "crates/x/target/"));
+        assert_eq!(None, ignored(&ignore, "target"));
+        assert_eq!(Some(true), ignored(&ignore, "tmp/a.o"));
+        assert_eq!(Some(true), ignored(&ignore, "a/b/tmp/a.o"));
+        assert_eq!(Some(true), ignored(&ignore, "logs/2024/jan.txt"));
+        assert_eq!(None, ignored(&ignore, "logs/"));
+    }
+
+    #[test]
+    fn negation_and_escapes() {
+        let ignore = Ignore::parse("*.log\n!keep.log\n\\!bang\n\\#hash\ntrailing  \n");
+        assert_eq!(Some(true), ignored(&ignore, "a.log"));
+        assert_eq!(Some(false), ignored(&ignore, "keep.log"));
+        assert_eq!(Some(true), ignored(&ignore, "!bang"));
+        assert_eq!(Some(true), ignored(&ignore, "#hash"));
+        assert_eq!(Some(true), ignored(&ignore, "trailing"));
+    }
+}
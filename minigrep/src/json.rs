@@ -0,0 +1,260 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::context::Line;
+use crate::error::MinigrepError;
+use crate::Config;
+
+// Output in the JSON Lines format for --json: one JSON object per line,
+// each describing one event, so other programs can read the results
+// without having to pick apart the normal output.
+//
+// Every object has a "type" and its "data". For each file with something
+// to print there is a "begin" event, a "match" or "context" event for each
+// line, then an "end" event. A "summary" event comes last of all:
+//
+//   {"type":"begin","data":{"path":{"text":"src/lib.rs"}}}
+//   {"type":"match","data":{"path":{"text":"src/lib.rs"},"lines":{"text":"fn main() {"},
+//    "line_number":3,"absolute_offset":42,"patterns":["main"],
+//    "submatches":[{"match":{"text":"main"},"start":3,"end":7}]}}
+//   {"type":"end","data":{"path":{"text":"src/lib.rs"},"stats":{"matched_lines":1}}}
+//   {"type":"summary","data":{"stats":{"searches":1,"searches_with_match":1,
+//    "matched_lines":1,"errors":0},"elapsed_secs":0.0012}}
+//
+// Paths and text are given as {"text": "..."} when they are valid UTF-8.
+// JSON strings can only hold Unicode, so anything else, like a file name
+// that isn't UTF-8, is given as {"bytes": "..."} with the raw bytes in
+// base64 instead. Line text doesn't include the line ending, offsets are
+// in bytes, and start and end are relative to the start of the line.
+
+// Writes the events for one file.
+pub(crate) struct JsonPrinter<'c> {
+    config: &'c Config,
+    // The path of the file, as raw bytes.
+    path: &'c [u8],
+    begun: bool,
+}
+
+impl<'c> JsonPrinter<'c> {
+    pub fn new(config: &'c Config, path: &'c [u8]) -> JsonPrinter<'c> {
+        JsonPrinter {
+            config,
+            path,
+            begun: false,
+        }
+    }
+
+    pub fn print(&mut self, out: &mut impl Write, line: &Line) -> io::Result<()> {
+        if !self.begun {
+            self.begun = true;
+            write!(out, r#"{{"type":"begin","data":{{"path":"#)?;
+            write_data(out, self.path)?;
+            writeln!(out, "}}}}")?;
+        }
+
+        // The line is written as it was read, so a line that isn't valid
+        // UTF-8 comes out as bytes, and the spans are offsets into it.
+        let (kind, byte_offset, m) = match line {
+            Line::Match(m) => ("match", m.byte_offset, Some(m)),
+            Line::Context { byte_offset, .. } => ("context", *byte_offset, None),
+        };
+        let bytes = line.bytes();
+        write!(out, r#"{{"type":"{kind}","data":{{"path":"#)?;
+        write_data(out, self.path)?;
+        write!(out, r#","lines":"#)?;
+        write_data(out, bytes)?;
+        write!(
+            out,
+            r#","line_number":{},"absolute_offset":{byte_offset},"patterns":["#,
+            line.line_number()
+        )?;
+        let patterns = m.map_or(&[][..], |m| &m.patterns);
+        for (i, &pattern) in patterns.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write_string(out, &self.config.patterns[pattern])?;
+        }
+        write!(out, "]")?;
+        if let Some(distance) = m.and_then(|m| m.distance) {
+            write!(out, r#","distance":{distance}"#)?;
+        }
+        write!(out, r#","submatches":["#)?;
+        let spans = m.map_or(&[][..], |m| &m.spans);
+        for (i, &(start, end)) in spans.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, r#"{{"match":"#)?;
+            write_data(out, &bytes[start..end])?;
+            write!(out, r#","start":{start},"end":{end}}}"#)?;
+        }
+        writeln!(out, "]}}}}")
+    }
+
+    // Ends the file, if anything was printed for it.
+    pub fn finish(&mut self, out: &mut impl Write, matched_lines: usize) -> io::Result<()> {
+        if !self.begun {
+            return Ok(());
+        }
+        write!(out, r#"{{"type":"end","data":{{"path":"#)?;
+        write_data(out, self.path)?;
+        writeln!(out, r#","stats":{{"matched_lines":{matched_lines}}}}}}}"#)
+    }
+}
+
+// The totals for the whole search, for the summary event.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub matched_lines: usize,
+    // What went wrong with each file that couldn't be searched.
+    pub errors: Vec<MinigrepError>,
+}
+
+pub(crate) fn write_summary(
+    out: &mut impl Write,
+    stats: &Stats,
+    elapsed: Duration,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"{{"type":"summary","data":{{"stats":{{"searches":{},"searches_with_match":{},"matched_lines":{},"errors":{}}},"elapsed_secs":{}}}}}"#,
+        stats.searches,
+        stats.searches_with_match,
+        stats.matched_lines,
+        stats.errors.len(),
+        elapsed.as_secs_f64()
+    )
+}
+
+// Writes bytes as {"text": ...} if they are UTF-8, or {"bytes": ...} in
+// base64 if not.
+fn write_data(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            write!(out, r#"{{"text":"#)?;
+            write_string(out, text)?;
+        }
+        Err(_) => write!(out, r#"{{"bytes":"{}""#, base64(bytes))?,
+    }
+    write!(out, "}}")
+}
+
+// Writes text as a JSON string, in quotes and with anything that can't
+// appear in a JSON string as it is escaped with a backslash.
+fn write_string(out: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            // Other control characters don't have a short form.
+            c if c < ' ' || c == '\u{7f}' => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    write!(out, "\"")
+}
+
+// Encodes bytes in base64, which uses 64 safe characters to write any
+// bytes as text: each 3 bytes become 4 characters of 6 bits each, with
+// '=' filling in at the end when there are fewer than 3 bytes left.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::searcher::Searcher;
+    use crate::test_config;
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = Vec::new();
+        write_string(&mut out, "say \"hi\"\\\n\t\u{1}é").unwrap();
+        assert_eq!(
+            r#""say \"hi\"\\\n\t\u0001é""#,
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn encodes_bytes_that_arent_text() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYg==", base64(b"foob"));
+
+        let mut out = Vec::new();
+        write_data(&mut out, b"caf\xe9").unwrap();
+        assert_eq!(r#"{"bytes":"Y2Fm6Q=="}"#, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn prints_events() {
+        let config = test_config(&["--json", "-A1", "b"]).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+        let searcher = Searcher::new(&matcher, 0, 1);
+        let mut out = Vec::new();
+        let mut json = JsonPrinter::new(&config, b"x.txt");
+        let count = searcher
+            .search_reader("a\nab\"\nc\n".as_bytes(), |line| {
+                json.print(&mut out, &line)
+            })
+            .unwrap();
+        json.finish(&mut out, count).unwrap();
+
+        let expected = [
+            r#"{"type":"begin","data":{"path":{"text":"x.txt"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"x.txt"},"lines":{"text":"ab\""},"line_number":2,"absolute_offset":2,"patterns":["b"],"submatches":[{"match":{"text":"b"},"start":1,"end":2}]}}"#,
+            r#"{"type":"context","data":{"path":{"text":"x.txt"},"lines":{"text":"c"},"line_number":3,"absolute_offset":6,"patterns":[],"submatches":[]}}"#,
+            r#"{"type":"end","data":{"path":{"text":"x.txt"},"stats":{"matched_lines":1}}}"#,
+        ];
+        assert_eq!(expected.join("\n") + "\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn lines_that_arent_utf8_are_bytes() {
+        let config = test_config(&["--json", "x"]).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+        let mut out = Vec::new();
+        let mut json = JsonPrinter::new(&config, b"x.txt");
+        Searcher::new(&matcher, 1, 0)
+            .search_reader(&b"\xff\ncaf\xe9 x\n"[..], |line| {
+                json.print(&mut out, &line)
+            })
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[1].contains(r#""lines":{"bytes":"/w=="}"#));
+        // "x" is at byte 5 of the line as it was read.
+        assert!(lines[2].contains(r#""lines":{"bytes":"Y2Fm6SB4"}"#));
+        assert!(lines[2].contains(r#"{"match":{"text":"x"},"start":5,"end":6}"#));
+    }
+}
@@ -1,16 +1,152 @@
-use std::env;
-use std::error::Error;
 use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+pub mod aho_corasick;
+mod args;
+pub mod context;
+pub mod encoding;
+pub mod error;
+pub mod fold;
+pub mod fuzzy;
+pub mod glob;
+pub mod ignore;
+mod json;
+pub mod literal;
+mod lossy;
+pub mod matcher;
+mod parallel;
+mod printer;
+pub mod regex;
+pub mod replace;
+pub mod searcher;
+pub mod types;
+pub mod walk;
+
+use crate::encoding::Encoding;
+use crate::error::MinigrepError;
+use crate::glob::Pattern;
+use crate::matcher::Matcher;
+use crate::regex::Regex;
+use crate::replace::Replacement;
+use crate::searcher::Searcher;
+use crate::types::Types;
+use crate::walk::WalkOptions;
 
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    pub action: Action,
+    // What to search for. There is usually just one pattern, the query,
+    // but more can be given with -e and -f.
+    pub patterns: Vec<String>,
+    // The files and directories to search, which may include globs
+    // like src/**/*.rs and STDIN_PATH for standard input.
+    pub file_paths: Vec<String>,
     pub ignore_case: bool,
+    // When set the query is compiled as a regular expression
+    // rather than being matched as a literal string.
+    pub regex: bool,
+    // Used when searching a directory, to decide which
+    // files below it get searched.
+    pub walk: WalkOptions,
+    // Extra information to print before each matching line.
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    // Whether to print the name of the file before each line. None means
+    // only when there could be more than one file: when there are several
+    // paths once globs are expanded, or a directory.
+    pub with_filename: Option<bool>,
+    // The name printed for lines read from standard input.
+    pub label: String,
+    // How many lines to print before and after each matching line.
+    pub before_context: usize,
+    pub after_context: usize,
+    // How many files to search at once, with 0 meaning one per CPU.
+    pub threads: usize,
+    // Print each file's results as soon as they are ready, rather than
+    // in the order the files were found.
+    pub unordered: bool,
+    // Print which of the patterns matched each line.
+    pub show_pattern: bool,
+    // Select the lines that don't match instead of those that do.
+    pub invert_match: bool,
+    // Stop searching a file after this many matching lines.
+    pub max_count: Option<usize>,
+    pub output: Output,
+    // Only match whole words, or only whole lines.
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    // Whether to highlight the output with colors. This is already worked
+    // out from --color, NO_COLOR and whether stdout is a terminal.
+    pub color: bool,
+    // Print JSON Lines describing the results, for other programs to read.
+    pub json: bool,
+    // What to replace each match with, as with --replace.
+    pub replace: Option<Replacement>,
+    // Write the replacements back to the files rather than printing them,
+    // keeping a copy of each original with the backup suffix added if
+    // there is one.
+    pub in_place: bool,
+    pub backup: Option<String>,
+    pub binary_files: BinaryFiles,
+    // The encoding of the files being searched. None means UTF-8, unless a
+    // file starts with a byte order mark saying otherwise.
+    pub encoding: Option<Encoding>,
+    // Match substrings up to this many edits away from the query, as
+    // with --fuzzy.
+    pub fuzzy: Option<usize>,
+}
+
+// The file path that means "read from standard input" instead.
+pub const STDIN_PATH: &str = "-";
+
+// The name used for standard input when file names are printed,
+// unless another is given with --label.
+pub const STDIN_LABEL: &str = "(standard input)";
+
+// What to print for each file searched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    // The matching lines themselves, with any context.
+    Lines,
+    // Just the number of matching lines.
+    Count,
+    // Just the name of the file, if it has a match.
+    FilesWithMatches,
+    // Just the name of the file, if it has no matches.
+    FilesWithoutMatches,
+}
+
+// What to do with binary files, which are files that contain a NUL byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFiles {
+    // Search them, but only say whether they match rather than printing
+    // the lines, which would be mostly garbage.
+    Binary,
+    // Search them like any other file.
+    Text,
+    // Skip them, as if they had no matches.
+    WithoutMatch,
+}
+
+// How the case of letters is treated, as chosen by -i, -s and -S.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Case {
+    Sensitive,
+    Insensitive,
+    // Decided by the patterns, see Config::smart_case.
+    Smart,
+}
+
+// What the program has been asked to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Search,
+    Help,
+    Version,
 }
 
 impl Config {
-    // The 'static lifetime indicates that the variable can last
-    // for the length of the program.
     // The type of the iterator that env::args returns is std::env::Args
     // and that type implements the Iterator trait and returns String values.
     // Here we have set args to have a generic type with trait bounds
@@ -18,62 +154,560 @@ impl Config {
     // This means that args can be any type that implements
     // Iterator and returns String items.
     // We add the mut since we will be mutating args by iterating over it.
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("not enough arguments");
-        }
+    // Errors are returned as a MinigrepError, usually BadArguments with a
+    // message saying what was wrong, ready to be shown to the user.
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, MinigrepError> {
         // Skip the initial argument as it's the path of the program running.
         args.next();
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        // Default options come first, so that the command line can
+        // override them, unless --no-config says to leave them out.
+        let parsed = args::parse(args)?;
+        let parsed = match parsed
+            .options
+            .iter()
+            .any(|(opt, _)| opt.long == "no-config")
+        {
+            true => parsed,
+            false => args::with_defaults(parsed)?,
         };
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
+        let mut action = Action::Search;
+        let mut case = None;
+        let mut regex = false;
+        let mut walk = WalkOptions::default();
+        let mut line_number = false;
+        let mut column = false;
+        let mut byte_offset = false;
+        let mut with_filename = None;
+        let mut label = String::from(STDIN_LABEL);
+        let mut after_context = None;
+        let mut before_context = None;
+        let mut both_context = None;
+        let mut threads = 0;
+        let mut unordered = false;
+        let mut patterns = Vec::new();
+        // A pattern file can be empty, so we can't tell from patterns alone
+        // whether any were given. -e and -f can't be defaults, so these
+        // only ever come from the command line.
+        let mut patterns_given = false;
+        let mut show_pattern = false;
+        let mut invert_match = false;
+        let mut max_count = None;
+        let mut output = Output::Lines;
+        let mut word_regexp = false;
+        let mut line_regexp = false;
+        let mut color = args::Color::Auto;
+        let mut json = false;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut backup = None;
+        let mut binary_files = BinaryFiles::Binary;
+        let mut encoding = None;
+        let mut types = Types::default();
+        let mut type_names = Vec::new();
+        let mut fuzzy = None;
+
+        // When an option is given more than once, the last one wins.
+        for (opt, value) in parsed.options {
+            match opt.long {
+                "ignore-case" => case = Some(Case::Insensitive),
+                "case-sensitive" => case = Some(Case::Sensitive),
+                "smart-case" => case = Some(Case::Smart),
+                "regex" => regex = true,
+                "regexp" => {
+                    patterns.push(value.unwrap_or_default());
+                    patterns_given = true;
+                }
+                "file" => {
+                    patterns_given = true;
+                    let path = value.unwrap_or_default();
+                    let contents = fs::read_to_string(&path)
+                        .map_err(|e| MinigrepError::reading_text(&path, e))?;
+                    patterns.extend(contents.lines().map(String::from));
+                }
+                "show-pattern" => show_pattern = true,
+                "invert-match" => invert_match = true,
+                "word-regexp" => word_regexp = true,
+                "line-regexp" => line_regexp = true,
+                "color" => color = args::color(opt, value)?,
+                "json" => json = true,
+                "replace" => replace = value.as_deref().map(Replacement::new),
+                "in-place" => in_place = true,
+                "backup" => backup = value,
+                "text" => binary_files = BinaryFiles::Text,
+                "binary-files" => binary_files = args::binary_files(opt, value)?,
+                "encoding" => encoding = args::encoding(opt, value)?,
+                "fuzzy" => fuzzy = Some(args::number(opt, value)?),
+                "max-count" => max_count = Some(args::number(opt, value)?),
+                "count" => output = Output::Count,
+                "files-with-matches" => output = Output::FilesWithMatches,
+                "files-without-match" => output = Output::FilesWithoutMatches,
+                "line-number" => line_number = true,
+                "column" => column = true,
+                "byte-offset" => byte_offset = true,
+                "with-filename" => with_filename = Some(true),
+                "no-filename" => with_filename = Some(false),
+                "label" => label = value.unwrap_or_default(),
+                "after-context" => after_context = Some(args::number(opt, value)?),
+                "before-context" => before_context = Some(args::number(opt, value)?),
+                "context" => both_context = Some(args::number(opt, value)?),
+                "max-depth" => walk.max_depth = Some(args::number(opt, value)?),
+                "follow" => walk.follow_symlinks = true,
+                "hidden" => walk.hidden = true,
+                "no-ignore" => walk.no_ignore = true,
+                "include" => walk.include.push(Pattern::new(&value.unwrap_or_default())),
+                "exclude" => walk.exclude.push(Pattern::new(&value.unwrap_or_default())),
+                "exclude-dir" => walk
+                    .exclude_dir
+                    .push(Pattern::new(&value.unwrap_or_default())),
+                "type" => type_names.push(value.unwrap_or_default()),
+                "type-add" => types.add(&value.unwrap_or_default())?,
+                "threads" => threads = args::number(opt, value)?,
+                "unordered" => unordered = true,
+                "help" => action = Action::Help,
+                "version" => action = Action::Version,
+                "no-config" => {}
+                _ => unreachable!("every option in args::OPTIONS is handled"),
+            }
+        }
+
+        // The flags take priority over the environment variables, which
+        // are only read if none of -i, -s and -S was given.
+        let case = match case {
+            Some(case) => case,
+            None if args::env_bool("IGNORE_CASE")? => Case::Insensitive,
+            None if args::env_bool("SMART_CASE")? => Case::Smart,
+            None => Case::Sensitive,
+        };
+        if json && output != Output::Lines {
+            return Err(String::from("--json can't be used with -c, -l or -L").into());
+        }
+        // Editing files replaces every match, so options that change which
+        // lines are matched or what gets printed don't make sense with it.
+        if in_place && replace.is_none() {
+            return Err(String::from("--in-place needs --replace").into());
+        }
+        if in_place && (json || invert_match || max_count.is_some() || output != Output::Lines) {
+            return Err(
+                String::from("--in-place can't be used with -v, -m, -c, -l, -L or --json").into(),
+            );
+        }
+        // Fuzzy matching compares the query a character at a time, so it
+        // can't work on a regex, and finds only the closest match in a line.
+        if fuzzy.is_some() && (regex || word_regexp || line_regexp || patterns.len() > 1) {
+            return Err(String::from(
+                "--fuzzy can't be used with -E, -w, -x or more than one pattern",
+            )
+            .into());
+        }
+        // Files are only ever written back out as UTF-8.
+        if in_place && encoding.is_some() {
+            return Err(String::from("--in-place can't be used with --encoding").into());
+        }
+        if backup.is_some() && !in_place {
+            return Err(String::from("--backup needs --in-place").into());
+        }
+        // Colors are only used automatically when a person is likely to be
+        // reading the output, and they haven't asked for no colors.
+        let color = match color {
+            args::Color::Always => true,
+            args::Color::Never => false,
+            args::Color::Auto => io::stdout().is_terminal() && !args::env_set("NO_COLOR"),
         };
+        // Types are looked up once all the options have been read, so that
+        // --type-add works wherever it is given.
+        for name in &type_names {
+            walk.include
+                .extend(types.globs(name)?.iter().map(|glob| Pattern::new(glob)));
+        }
+        // -A and -B win over -C, whichever order they are given in.
+        let after_context = after_context.or(both_context).unwrap_or(0);
+        let before_context = before_context.or(both_context).unwrap_or(0);
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
-        println!("ignore case: {}", ignore_case);
+        // When the patterns come from -e or -f, every positional argument
+        // is a file path. Otherwise the first one is the query.
+        let mut positional = parsed.positional.into_iter();
+        if !patterns_given {
+            match (action, positional.next()) {
+                (Action::Search, Some(arg)) => patterns.push(arg),
+                (Action::Search, None) => {
+                    return Err(String::from("Didn't get a query string").into())
+                }
+                (_, _) => {}
+            }
+        }
+        // With as many edits as the query has characters, the whole query
+        // could be deleted, and every line would match with nothing in it.
+        if let (Some(max_edits), Some(query)) = (fuzzy, patterns.first()) {
+            if max_edits >= query.chars().count() {
+                return Err(String::from(
+                    "--fuzzy needs fewer edits than the query has characters",
+                )
+                .into());
+            }
+        }
+        // Without a file path we read from standard input, so that
+        // minigrep can be used at the end of a pipeline.
+        let ignore_case = match case {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => Config::smart_case(&patterns, regex),
+        };
+        let mut file_paths: Vec<String> = positional.collect();
+        if file_paths.is_empty() {
+            file_paths.push(String::from(STDIN_PATH));
+        }
+        if in_place && file_paths.iter().any(|path| path == STDIN_PATH) {
+            return Err(String::from("--in-place can't edit standard input").into());
+        }
 
         Ok(Config {
-            query,
-            file_path,
+            action,
+            patterns,
+            file_paths,
             ignore_case,
+            regex,
+            walk,
+            line_number,
+            column,
+            byte_offset,
+            with_filename,
+            label,
+            before_context,
+            after_context,
+            threads,
+            unordered,
+            show_pattern,
+            invert_match,
+            max_count,
+            output,
+            word_regexp,
+            line_regexp,
+            color,
+            json,
+            replace,
+            in_place,
+            backup,
+            binary_files,
+            encoding,
+            fuzzy,
+        })
+    }
+
+    // Smart case matches without regard to case unless a pattern has an
+    // uppercase letter in it, on the grounds that someone who typed one
+    // probably meant it. Returns whether case should be ignored. In a
+    // regex, the letter after a '\' doesn't count, since \W and \S are
+    // classes of characters rather than uppercase letters.
+    pub fn smart_case(patterns: &[String], regex: bool) -> bool {
+        !patterns.iter().any(|pattern| {
+            let mut chars = pattern.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' && regex {
+                    chars.next();
+                } else if c.is_uppercase() {
+                    return true;
+                }
+            }
+            false
         })
     }
+
+    // Whether lines of context are printed around matches.
+    pub fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+}
+
+// The run function returns whether anything matched, or a MinigrepError
+// saying what went wrong, so that main can exit with the same status as
+// grep: 0 when something matched, 1 when nothing did and 2 on an error.
+pub fn run(config: Config) -> Result<bool, MinigrepError> {
+    match config.action {
+        Action::Search => {}
+        Action::Help => {
+            print!("{}", args::usage());
+            return Ok(true);
+        }
+        Action::Version => {
+            println!("minigrep {}", env!("CARGO_PKG_VERSION"));
+            return Ok(true);
+        }
+    }
+    search_to(&config, io::stdout().lock())
 }
 
-// The run function returns unit type (), or Box<dyn Error>
-// Box<dyn Error> means that it will return a type that
-// implements the Error trait, but we don't have to
-// specify what particular type that will be.
-// This provides flexibility to return errors of different
-// types in different cases. The dyn keyword is short for dynamic.
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    // Use the ? to return the error value on error.
-    let contents = fs::read_to_string(config.file_path)?;
+// Does the search for run, writing the results to out.
+fn search_to(config: &Config, out: impl io::Write) -> Result<bool, MinigrepError> {
+    // A bad pattern is returned as an error here rather than
+    // panicking, so main can report it like any other error.
+    let matcher = Matcher::new(config)?;
+    let searcher = Searcher::for_config(&matcher, config);
 
-    println!("ignore case: {}", config.ignore_case);
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+    // Globs are expanded first, so that we know how many files there are
+    // and whether their names need printing.
+    let mut failed = Vec::new();
+    let mut inputs = Vec::new();
+    for arg in &config.file_paths {
+        match expand_input(arg) {
+            Ok(expanded) => inputs.extend(expanded),
+            Err(e) => {
+                eprintln!("minigrep: {e}");
+                failed.push(e);
+            }
+        }
+    }
+    let with_filename = with_filename(config, &inputs);
+
+    // A file we can't read shouldn't stop the rest of the files being
+    // searched, so problems are reported as they happen and we carry on.
+    // Files are read a line at a time as they are searched, rather than
+    // being loaded into memory all at once, so huge files are fine.
+    let stats = match parallel::search_all(config, &searcher, inputs, with_filename, out) {
+        Ok(stats) => stats,
+        // When our output is piped into something like head, which exits
+        // once it has read enough, writing fails with a broken pipe. That
+        // isn't worth reporting, since whoever wanted the output has it.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(true),
+        Err(e) => {
+            return Err(MinigrepError::Io {
+                path: PathBuf::from("(standard output)"),
+                source: e,
+            })
+        }
+    };
+
+    // As with grep, an error wins even if other files matched, since a
+    // file that couldn't be searched might have matched too.
+    failed.extend(stats.errors);
+    if !failed.is_empty() {
+        return Err(MinigrepError::Incomplete { failed });
+    }
+    // With -L, what's being looked for is files without a match.
+    let matched = match config.output {
+        Output::FilesWithoutMatches => stats.searches_with_match < stats.searches,
+        _ => stats.searches_with_match > 0,
+    };
+    Ok(matched)
+}
+
+// Whether to print the name of the file before each line, when it hasn't
+// been decided with -H or -h: only when there could be more than one file.
+// Standard input counts as a file, named by --label.
+fn with_filename(config: &Config, inputs: &[Input]) -> bool {
+    config.with_filename.unwrap_or_else(|| {
+        inputs.len() > 1
+            || inputs
+                .iter()
+                .any(|input| matches!(input, Input::Path(path) if path.is_dir()))
+    })
+}
+
+// Somewhere to read text to search from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Input {
+    Stdin,
+    // A file, or a directory to search all the files in.
+    Path(PathBuf),
+}
+
+// Turns one of the file path arguments into the inputs to search.
+// An argument that names an existing file is used as it is, even if it
+// looks like a glob.
+fn expand_input(arg: &str) -> Result<Vec<Input>, MinigrepError> {
+    if arg == STDIN_PATH {
+        return Ok(vec![Input::Stdin]);
+    }
+    if !glob::is_glob(arg) || Path::new(arg).exists() {
+        return Ok(vec![Input::Path(PathBuf::from(arg))]);
+    }
+    let source = match glob::expand(arg) {
+        Ok(paths) if paths.is_empty() => {
+            io::Error::new(io::ErrorKind::NotFound, "no files match this pattern")
+        }
+        Ok(paths) => return Ok(paths.into_iter().map(Input::Path).collect()),
+        Err(e) => e,
     };
+    Err(MinigrepError::Io {
+        path: PathBuf::from(arg),
+        source,
+    })
+}
 
-    for line in results {
-        println!("{line}");
+// A line that matched the query, along with where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    // The matching line, without its line ending.
+    pub line: &'a str,
+    // Line numbers start at 1, like in a text editor.
+    pub line_number: usize,
+    // The byte offset of the start of the line from the start of the contents.
+    pub byte_offset: usize,
+    // The column of the first match in the line, counted in characters
+    // and starting at 1.
+    pub column: usize,
+    // The byte ranges within the line of each non-overlapping match. They
+    // are offsets into what was read, which is only different from line
+    // when raw is set.
+    pub spans: Vec<(usize, usize)>,
+    // The indexes of the patterns found in the line, in the order they
+    // were first found, each listed only once.
+    pub patterns: Vec<usize>,
+    // For a fuzzy match, how many edits away from the query it is.
+    pub distance: Option<usize>,
+    // The bytes that were read, when they weren't valid UTF-8 and line is
+    // only their lossy decoding. None means line is exactly what was read.
+    pub raw: Option<&'a [u8]>,
+}
+
+impl<'a> Match<'a> {
+    // Builds a Match from the (start, end, pattern) spans a matcher found
+    // in a line, or returns None if it didn't find any.
+    pub fn new(
+        line: &'a str,
+        line_number: usize,
+        byte_offset: usize,
+        found: Vec<(usize, usize, usize)>,
+    ) -> Option<Match<'a>> {
+        Match::build(line, None, line_number, byte_offset, found)
     }
 
-    // If success, return unit type ()
-    Ok(())
+    fn build(
+        line: &'a str,
+        raw: Option<&'a [u8]>,
+        line_number: usize,
+        byte_offset: usize,
+        found: Vec<(usize, usize, usize)>,
+    ) -> Option<Match<'a>> {
+        let first = found.first()?.0;
+        let column = match raw {
+            Some(raw) => lossy::char_count(&raw[..first]) + 1,
+            None => line[..first].chars().count() + 1,
+        };
+        let mut patterns = Vec::new();
+        for &(_, _, pattern) in &found {
+            if !patterns.contains(&pattern) {
+                patterns.push(pattern);
+            }
+        }
+        // An empty match (from an empty query, say) still means the line
+        // matched, but there is nothing in it worth pointing at.
+        let spans = found
+            .into_iter()
+            .filter(|(start, end, _)| start < end)
+            .map(|(start, end, _)| (start, end))
+            .collect();
+        Some(Match {
+            line,
+            line_number,
+            byte_offset,
+            column,
+            spans,
+            patterns,
+            distance: None,
+            raw,
+        })
+    }
+
+    // Builds the Match for whatever matcher finds in line, if anything.
+    pub fn find(
+        matcher: &Matcher,
+        line: &'a str,
+        line_number: usize,
+        byte_offset: usize,
+    ) -> Option<Match<'a>> {
+        let (found, distance) = find_spans(matcher, line);
+        let mut m = Match::new(line, line_number, byte_offset, found)?;
+        m.distance = distance;
+        Some(m)
+    }
+
+    // Like find, for a line read as raw that wasn't valid UTF-8, where line
+    // is its lossy decoding. Bytes that aren't valid UTF-8 aren't any
+    // character, so only matches that don't take in any of them count, and
+    // their spans point into raw.
+    pub fn find_lossy(
+        matcher: &Matcher,
+        raw: &'a [u8],
+        line: &'a str,
+        line_number: usize,
+        byte_offset: usize,
+    ) -> Option<Match<'a>> {
+        let (found, distance) = find_spans(matcher, line);
+        let found = found
+            .into_iter()
+            .filter_map(|(start, end, pattern)| {
+                let (start, end) = lossy::to_bytes(raw, (start, end))?;
+                Some((start, end, pattern))
+            })
+            .collect();
+        let mut m = Match::build(line, Some(raw), line_number, byte_offset, found)?;
+        m.distance = distance;
+        Some(m)
+    }
+
+    // The line as it was read.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.raw.unwrap_or(self.line.as_bytes())
+    }
+
+    // Where a span is in line, for printing it.
+    pub fn line_span(&self, span: (usize, usize)) -> (usize, usize) {
+        match self.raw {
+            Some(raw) => lossy::to_text(raw, span),
+            None => span,
+        }
+    }
+}
+
+// What matcher finds in line, along with how close the match was for a
+// fuzzy matcher, which find_all can't say.
+fn find_spans(matcher: &Matcher, line: &str) -> (Vec<(usize, usize, usize)>, Option<usize>) {
+    match matcher {
+        Matcher::Fuzzy(fuzzy) => match fuzzy.find(line) {
+            Some(found) => (vec![(found.start, found.end, 0)], Some(found.distance)),
+            None => (Vec::new(), None),
+        },
+        _ => (matcher.find_all(line), None),
+    }
+}
+
+// Removes the "\n" or "\r\n" from the end of a line.
+pub(crate) fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Splits contents into lines like str::lines does, but also gives
+// the byte offset where each line starts.
+pub(crate) fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    contents.split_inclusive('\n').map(move |line| {
+        let start = offset;
+        offset += line.len();
+        (start, trim_line_ending(line))
+    })
 }
 
-// Note that here we clarify that the lifetime of the return strings will
+// Searches contents that are already in memory, building a Match for each
+// line where the matcher finds something.
+pub fn search_matcher<'a>(matcher: &Matcher, contents: &'a str) -> Vec<Match<'a>> {
+    // Plain text can be found faster by searching all of contents at once.
+    if let Matcher::Literal(finder) = matcher {
+        return literal::search(finder, contents);
+    }
+    lines_with_offsets(contents)
+        .enumerate()
+        .filter_map(|(index, (byte_offset, line))| {
+            Match::find(matcher, line, index + 1, byte_offset)
+        })
+        .collect()
+}
+
+// Note that here we clarify that the lifetime of the returned matches will
 // be the lifetime of the contents, and not the query.
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
     // let mut results = Vec::new();
     // for line in contents.lines() {
     //     // search for the query in the line
@@ -84,29 +718,205 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     // results
 
     // Use iterators instead
-    contents
-        .lines()
-        .filter(|line: &&str| line.contains(query))
-        .collect()
+    search_matcher(&Matcher::literal(query), contents)
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(&Matcher::case_insensitive(query), contents)
+}
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
+pub fn search_regex<'a>(re: &Regex, contents: &'a str) -> Vec<Match<'a>> {
+    search_matcher(&Matcher::Regex(re.clone()), contents)
+}
+
+// Builds a Config from the arguments after the program name, for the
+// tests. Nothing is taken from the developer's own settings: --no-config
+// leaves out the config file and MINIGREP_OPTS, and since -s and
+// --color=never are given, IGNORE_CASE, SMART_CASE and NO_COLOR are never
+// looked at. A case or color option in args still wins, coming later.
+#[cfg(test)]
+pub(crate) fn test_config(args: &[&str]) -> Result<Config, MinigrepError> {
+    let defaults = ["minigrep", "--no-config", "-s", "--color=never"];
+    Config::build(defaults.iter().chain(args).map(|arg| arg.to_string()))
+}
+
+// A directory under the system temp directory for the tests that need
+// real files. It is removed again when it is dropped, so that nothing is
+// left behind even when a test fails.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    // Creates an empty directory, named after the test and our process
+    // so that tests running at the same time don't get in each other's way.
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("minigrep-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
     }
+}
 
-    results
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // The search functions return a Match for each line, but most of
+    // the tests only care about which lines matched.
+    fn lines<'a>(matches: Vec<Match<'a>>) -> Vec<&'a str> {
+        matches.into_iter().map(|m| m.line).collect()
+    }
+
+    #[test]
+    fn build_config_from_flags() {
+        let args = ["-inC2", "-A", "1", "--", "-query", "poem.txt"];
+        let config = test_config(&args).unwrap();
+
+        assert_eq!(vec!["-query"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+        assert!(config.ignore_case);
+        assert!(config.line_number);
+        assert_eq!((2, 1), (config.before_context, config.after_context));
+
+        // Standard input is searched when there is no file path.
+        let args = ["query"];
+        let config = test_config(&args).unwrap();
+        assert_eq!(vec![STDIN_PATH], config.file_paths);
+
+        // With -e, every positional argument is a file path.
+        let args = ["-e", "one", "--regexp=two", "poem.txt"];
+        let config = test_config(&args).unwrap();
+        assert_eq!(vec!["one", "two"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+
+        // With -S, an uppercase letter anywhere in the patterns makes the
+        // search case sensitive, but an escape like \W doesn't.
+        for (args, ignore_case) in [
+            (&["-S", "query"][..], true),
+            (&["-S", "Query"], false),
+            (&["-i", "-S", "-e", "a", "-e", "B"], false),
+            (&["-SE", r"\W+a"], true),
+            (&["-S", "-i", "Query"], true),
+        ] {
+            let config = test_config(args).unwrap();
+            assert_eq!(ignore_case, config.ignore_case, "{args:?}");
+        }
+
+        let error = test_config(&[]).err();
+        assert!(matches!(
+            error,
+            Some(MinigrepError::BadArguments(message)) if message == "Didn't get a query string"
+        ));
+    }
+
+    #[test]
+    fn says_whether_anything_matched() {
+        let dir = TempDir::new("run-result");
+        fs::write(dir.join("poem.txt"), "frog\ntoad\n").unwrap();
+        let poem = dir.join("poem.txt").display().to_string();
+        let result = |args: &[&str]| {
+            let args: Vec<_> = args.iter().copied().chain([poem.as_str()]).collect();
+            search_to(&test_config(&args).unwrap(), Vec::new())
+        };
+        assert!(result(&["frog"]).unwrap());
+        assert!(!result(&["newt"]).unwrap());
+        // With -L, a file without a match is what counts.
+        assert!(result(&["-L", "newt"]).unwrap());
+        assert!(!result(&["-L", "frog"]).unwrap());
+
+        // A file that can't be searched is an error even when another
+        // matched, and what went wrong with it is kept.
+        let missing = dir.join("missing.txt").display().to_string();
+        let config = test_config(&["frog", &missing, &poem]).unwrap();
+        let error = search_to(&config, Vec::new()).err();
+        let Some(MinigrepError::Incomplete { failed }) = error else {
+            panic!("expected an incomplete search, got {error:?}");
+        };
+        assert!(matches!(
+            &failed[..],
+            [MinigrepError::Io { path, source }]
+                if *path == Path::new(&missing) && source.kind() == io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn fuzzy_cant_match_everything() {
+        assert_eq!(Some(1), test_config(&["--fuzzy", "1", "ab"]).unwrap().fuzzy);
+        for args in [
+            &["--fuzzy", "2", "ab"][..],
+            &["--fuzzy", "9", "ab"],
+            &["--fuzzy", "0", ""],
+        ] {
+            let error = test_config(args).err();
+            assert!(matches!(
+                error,
+                Some(MinigrepError::BadArguments(message))
+                    if message == "--fuzzy needs fewer edits than the query has characters"
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_stdin_without_a_path_or_with_dash() {
+        for args in [&["query"][..], &["query", "-"]] {
+            let config = test_config(args).unwrap();
+            assert_eq!(vec![STDIN_PATH], config.file_paths, "{args:?}");
+            assert_eq!(
+                vec![Input::Stdin],
+                expand_input(&config.file_paths[0]).unwrap()
+            );
+        }
+        let config = test_config(&["query", "-", "poem.txt"]).unwrap();
+        let inputs: Vec<_> = config
+            .file_paths
+            .iter()
+            .flat_map(|path| expand_input(path).unwrap())
+            .collect();
+        assert_eq!(
+            vec![Input::Stdin, Input::Path(PathBuf::from("poem.txt"))],
+            inputs
+        );
+    }
+
+    #[test]
+    fn names_stdin_among_files() {
+        let stdin_and_file = [Input::Stdin, Input::Path(PathBuf::from("poem.txt"))];
+        let config = test_config(&["query"]).unwrap();
+        assert_eq!(STDIN_LABEL, config.label);
+        assert!(with_filename(&config, &stdin_and_file));
+        assert!(!with_filename(&config, &[Input::Stdin]));
+
+        let config = test_config(&["-H", "--label", "pipe", "query"]).unwrap();
+        assert_eq!("pipe", config.label);
+        assert!(with_filename(&config, &[Input::Stdin]));
+        let config = test_config(&["-h", "query"]).unwrap();
+        assert!(!with_filename(&config, &stdin_and_file));
+    }
+
     #[test]
     fn case_sensitive() {
         let query = "duct";
@@ -117,7 +927,10 @@ Rust:
 safe, fast, productive.
 Pick three.";
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            lines(search(query, contents))
+        );
     }
 
     #[test]
@@ -131,7 +944,49 @@ Trust me.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            lines(search_case_insensitive(query, contents))
         );
     }
+
+    #[test]
+    fn regex() {
+        let re = Regex::new(r"^\w+:$|three|[Tt]rust").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "Pick three.", "Trust me."],
+            lines(search_regex(&re, contents))
+        );
+    }
+
+    #[test]
+    fn match_positions() {
+        let contents = "first line\r\nsecond: éé then é\n";
+
+        assert_eq!(
+            vec![Match {
+                line: "second: éé then é",
+                line_number: 2,
+                byte_offset: 12,
+                column: 9,
+                spans: vec![(8, 10), (10, 12), (18, 20)],
+                patterns: vec![0],
+                distance: None,
+                raw: None,
+            }],
+            search("é", contents)
+        );
+
+        // Spans point into the original line even when case folding
+        // changes the length of the text before or inside the match.
+        let matches = search_case_insensitive("x", "İİx");
+        assert_eq!(vec![(4, 5)], matches[0].spans);
+        assert_eq!(3, matches[0].column);
+        let matches = search_case_insensitive("SS", "Maß");
+        assert_eq!(vec![(2, 4)], matches[0].spans);
+    }
 }
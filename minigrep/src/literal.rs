@@ -0,0 +1,351 @@
+use crate::{trim_line_ending, Match};
+
+// Fast searching for a plain piece of text.
+//
+// Two tricks do most of the work:
+//
+// - Rather than comparing the query at every position, we pick out the
+//   byte of the query that is likely to be rarest in ordinary text, like
+//   the 'z' in "lazy", and look for that byte on its own (see find_byte),
+//   which can be done eight bytes at a time. The whole query is only
+//   compared where that byte turns up, so most of the text is skipped
+//   over very cheaply.
+//
+// - When the rare byte turns up a lot anyway, the Boyer-Moore-Horspool
+//   algorithm takes over. It compares the last byte of the query first,
+//   and a table built up front says how far the query can be moved along
+//   for each possible byte that might be found there. If that byte doesn't
+//   appear in the query at all, the query can jump its whole length
+//   forward in one step.
+//
+// Both work on bytes rather than chars. Since the query and the text are
+// both valid UTF-8, a match can only start and end on char boundaries.
+
+// How many false alarms from the rare byte scan we put up with before
+// switching to Horspool for the rest of the text.
+const MAX_FALSE_ALARMS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finder {
+    needle: String,
+    // Where the rarest byte of the needle is.
+    rare: usize,
+    // How far to move along when the byte under the last byte of the
+    // needle is the index into this table.
+    skip: Vec<usize>,
+}
+
+impl Finder {
+    pub fn new(needle: &str) -> Finder {
+        let bytes = needle.as_bytes();
+        let mut skip = vec![bytes.len().max(1); 256];
+        // The last byte is left out, since lining it up with itself
+        // wouldn't move the needle along at all.
+        for (i, &b) in bytes.iter().enumerate().take(bytes.len().saturating_sub(1)) {
+            skip[b as usize] = bytes.len() - 1 - i;
+        }
+        let rare = (0..bytes.len())
+            .min_by_key(|&i| commonness(bytes[i]))
+            .unwrap_or(0);
+        Finder {
+            needle: needle.to_string(),
+            rare,
+            skip,
+        }
+    }
+
+    pub fn needle(&self) -> &str {
+        &self.needle
+    }
+
+    // Returns where the needle next appears in haystack, at or after at.
+    pub fn find_at(&self, haystack: &[u8], at: usize) -> Option<usize> {
+        let needle = self.needle.as_bytes();
+        let n = needle.len();
+        if n == 0 {
+            return (at <= haystack.len()).then_some(at);
+        }
+
+        let mut at = at;
+        let mut false_alarms = 0;
+        while at + n <= haystack.len() {
+            if false_alarms > MAX_FALSE_ALARMS && n > 1 {
+                return self.horspool(haystack, at);
+            }
+            // Only look where the whole needle would still fit.
+            let window = &haystack[at + self.rare..haystack.len() - n + 1 + self.rare];
+            at += find_byte(needle[self.rare], window)?;
+            if &haystack[at..at + n] == needle {
+                return Some(at);
+            }
+            false_alarms += 1;
+            at += 1;
+        }
+        None
+    }
+
+    fn horspool(&self, haystack: &[u8], mut at: usize) -> Option<usize> {
+        let needle = self.needle.as_bytes();
+        let n = needle.len();
+        let last = needle[n - 1];
+        while at + n <= haystack.len() {
+            let b = haystack[at + n - 1];
+            if b == last && haystack[at..at + n - 1] == needle[..n - 1] {
+                return Some(at);
+            }
+            at += self.skip[b as usize];
+        }
+        None
+    }
+
+    // Returns the byte ranges of the non-overlapping matches in line.
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize)> {
+        self.find_iter_at(line, 0).collect()
+    }
+
+    // Like find_all, but only looks at or after at, for when the first
+    // match is already known to be there. The matches are found as they
+    // are asked for, so they can go straight into whatever is collecting
+    // them without a Vec in between.
+    pub fn find_iter_at<'s>(
+        &'s self,
+        line: &'s str,
+        mut at: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + 's {
+        let n = self.needle.len();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            // An empty needle matches every line, at the very start, but
+            // only once.
+            if done {
+                return None;
+            }
+            let start = self.find_at(line.as_bytes(), at)?;
+            done = n == 0;
+            at = start + n;
+            Some((start, start + n))
+        })
+    }
+}
+
+// Searches contents for lines containing the needle. Rather than looking
+// at each line in turn, this searches the whole of contents for the
+// needle, and only works out where the line is once something is found.
+// Lines without a match are never even split out, which is where most of
+// the time goes when matches are rare.
+pub fn search<'a>(finder: &Finder, contents: &'a str) -> Vec<Match<'a>> {
+    LinesWith::new(finder, contents.as_bytes())
+        .filter_map(|(index, start, end, hit)| {
+            // The rest of the line is searched from the hit, to pick up any
+            // other matches later in it. A needle containing a line ending
+            // could have matched across lines, which this also rules out.
+            let line = trim_line_ending(&contents[start..end]);
+            let found = finder.find_iter_at(line, hit - start);
+            let found = found.map(|(start, end)| (start, end, 0));
+            Match::new(line, index + 1, start, found.collect())
+        })
+        .collect()
+}
+
+// The lines of some bytes that the needle turns up in, found by searching
+// all of the bytes at once. Each is given as the number of lines before
+// it, where it starts and ends, leaving out the "\n" at the end, and where
+// the first match in it starts. The Searcher uses this on each buffer it
+// reads, as search does on text that is already in memory.
+pub(crate) struct LinesWith<'f, 'b> {
+    finder: &'f Finder,
+    bytes: &'b [u8],
+    // Always the start of a line, with lines being the number before it.
+    at: usize,
+    lines: usize,
+}
+
+impl<'f, 'b> LinesWith<'f, 'b> {
+    pub fn new(finder: &'f Finder, bytes: &'b [u8]) -> LinesWith<'f, 'b> {
+        LinesWith {
+            finder,
+            bytes,
+            at: 0,
+            lines: 0,
+        }
+    }
+
+    // The number of "\n"s in all of the bytes, for once the search is over.
+    pub fn line_count(&self) -> usize {
+        let rest = &self.bytes[self.at.min(self.bytes.len())..];
+        self.lines + count_byte(b'\n', rest)
+    }
+}
+
+impl Iterator for LinesWith<'_, '_> {
+    type Item = (usize, usize, usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize, usize, usize)> {
+        let bytes = self.bytes;
+        let at = self.at;
+        if at >= bytes.len() {
+            return None;
+        }
+        let found = self.finder.find_at(bytes, at)?;
+        // Counting the lines skipped over also turns up where the last of
+        // them ends, which is where the line with the hit starts.
+        let mut start = at;
+        let mut index = self.lines;
+        while let Some(i) = find_byte(b'\n', &bytes[start..found]) {
+            start += i + 1;
+            index += 1;
+        }
+        let end = match find_byte(b'\n', &bytes[found..]) {
+            Some(i) => found + i,
+            None => bytes.len(),
+        };
+        self.lines = index + 1;
+        self.at = end + 1;
+        Some((index, start, end, found))
+    }
+}
+
+// Finds the first place byte appears in haystack. Rather than comparing a
+// byte at a time, this loads eight bytes at once into a u64 and uses some
+// arithmetic to tell whether any of them is the byte we want.
+pub(crate) fn find_byte(byte: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = ONES * byte as u64;
+    let mut blocks = haystack.chunks_exact(32);
+    let mut offset = 0;
+    for block in &mut blocks {
+        // Four words are checked together, since a block without the byte
+        // in it, which is most of them, then costs only one branch.
+        let words: [u64; 4] = std::array::from_fn(|i| word(&block[i * 8..]) ^ repeated);
+        if words.iter().any(|&w| has_zero(w) != 0) {
+            break;
+        }
+        offset += 32;
+    }
+    for chunk in haystack[offset..].chunks(8) {
+        if chunk.len() < 8 {
+            let rest = chunk.iter().position(|&b| b == byte)?;
+            return Some(offset + rest);
+        }
+        let zeros = has_zero(word(chunk) ^ repeated);
+        if zeros != 0 {
+            // The lowest flagged byte is always a real zero, so it tells
+            // us where the first match is.
+            return Some(offset + zeros.trailing_zeros() as usize / 8);
+        }
+        offset += 8;
+    }
+    None
+}
+
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+fn word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+// Sets the top bit of each byte of word that might be zero. XORing the
+// byte we're looking for into every byte of a word turns matches into
+// zeros. Subtracting one from each byte then borrows from a zero byte,
+// setting its top bit, which isn't set in the original. A borrow can also
+// flag the byte above a real zero, but never one below it.
+fn has_zero(word: u64) -> u64 {
+    word.wrapping_sub(ONES) & !word & HIGHS
+}
+
+// A rough guess at how often byte turns up in the sort of text people
+// search: spaces and common lowercase letters most of all, then other
+// letters, with punctuation, digits and control characters less often.
+fn commonness(byte: u8) -> u8 {
+    match byte {
+        b' ' => 255,
+        b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'h' | b'r' => 240,
+        b'd' | b'l' | b'c' | b'u' | b'm' | b'w' | b'f' | b'g' | b'y' | b'p' => 200,
+        b'a'..=b'z' => 150,
+        b'\n' | b'\t' | b'.' | b',' | b'_' | b'(' | b')' | b';' | b':' => 140,
+        b'A'..=b'Z' => 100,
+        b'0'..=b'9' => 90,
+        0x80.. => 60,
+        _ => 50,
+    }
+}
+
+fn count_byte(byte: u8, haystack: &[u8]) -> usize {
+    haystack.iter().filter(|&&b| b == byte).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::search_matcher;
+
+    #[test]
+    fn finds_bytes() {
+        // Long enough to go through the 32 and 8 byte loops and the
+        // leftover bytes at the end.
+        let haystack: Vec<u8> = (0..77).collect();
+        for (i, &b) in haystack.iter().enumerate() {
+            assert_eq!(Some(i), find_byte(b, &haystack));
+            assert_eq!(Some(0), find_byte(b, &haystack[i..]));
+        }
+        assert_eq!(None, find_byte(200, &haystack));
+        assert_eq!(Some(1), find_byte(b'x', b"axxx"));
+        // A zero just before the byte wanted mustn't be mistaken for it.
+        assert_eq!(Some(9), find_byte(1, &[0, 0, 0, 0, 0, 0, 0, 2, 0, 1]));
+    }
+
+    #[test]
+    fn finds_short_and_long_needles() {
+        let haystack = "the cat sat on the mat with another cat";
+        for needle in [
+            "c", "at", "the", "cat", "another", "mat with", "dog", "cats",
+        ] {
+            let expected: Vec<_> = haystack
+                .match_indices(needle)
+                .map(|(i, m)| (i, i + m.len()))
+                .collect();
+            assert_eq!(expected, Finder::new(needle).find_all(haystack), "{needle}");
+        }
+
+        let finder = Finder::new("cat");
+        let found: Vec<_> = finder.find_iter_at(haystack, 5).collect();
+        assert_eq!(vec![(36, 39)], found);
+        // An empty needle matches once, where the search starts.
+        let found: Vec<_> = Finder::new("").find_iter_at(haystack, 4).collect();
+        assert_eq!(vec![(4, 4)], found);
+    }
+
+    #[test]
+    fn switches_to_horspool_after_false_alarms() {
+        // The 'z' is the rare byte, but it keeps turning up without a match.
+        let haystack = "lazy cat, ".repeat(100) + "lazy dog";
+        assert_eq!(
+            Some(1000),
+            Finder::new("lazy dog").find_at(haystack.as_bytes(), 0)
+        );
+        assert_eq!(
+            None,
+            Finder::new("lazy fox").find_at(haystack.as_bytes(), 0)
+        );
+    }
+
+    #[test]
+    fn whole_buffer_search_agrees_with_line_by_line() {
+        let contents = "one fish\ntwo fish\r\nred\n\nblue fish fish\nfisherman";
+        for needle in ["fish", "f", "sh\r", "h\nt", "", "red", "nothing here"] {
+            let finder = Finder::new(needle);
+            let by_line: Vec<Match> = crate::lines_with_offsets(contents)
+                .enumerate()
+                .filter_map(|(i, (offset, line))| {
+                    let found = finder.find_all(line).into_iter();
+                    let found = found.map(|(start, end)| (start, end, 0)).collect();
+                    Match::new(line, i + 1, offset, found)
+                })
+                .collect();
+            let matcher = Matcher::Literal(finder.clone());
+            assert_eq!(by_line, search(&finder, contents), "{needle:?}");
+            assert_eq!(by_line, search_matcher(&matcher, contents), "{needle:?}");
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Lines that aren't valid UTF-8 are searched and printed as their lossy
+// decoding, where each invalid sequence of bytes becomes a single U+FFFD,
+// the replacement character, the same as String::from_utf8_lossy does.
+// These convert between byte offsets in the decoded text and in the bytes
+// that were actually read, so that what gets reported is where things are
+// in the file.
+
+const REPLACEMENT_LEN: usize = char::REPLACEMENT_CHARACTER.len_utf8();
+
+// Maps a span of the decoded text back to the bytes it came from. A span
+// that takes in a replacement character standing for invalid bytes gives
+// None, since those bytes weren't any character at all, so nothing should
+// match them.
+pub fn to_bytes(bytes: &[u8], (start, end): (usize, usize)) -> Option<(usize, usize)> {
+    let mut raw = 0;
+    let mut text = 0;
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if text <= start && end <= text + valid {
+            return Some((raw + start - text, raw + end - text));
+        }
+        raw += valid + chunk.invalid().len();
+        text += valid + REPLACEMENT_LEN * !chunk.invalid().is_empty() as usize;
+    }
+    // An empty match right at the end, after an invalid sequence.
+    (start == text && end == text).then_some((raw, raw))
+}
+
+// Maps a span of bytes, as given by to_bytes, to the decoded text.
+pub fn to_text(bytes: &[u8], (start, end): (usize, usize)) -> (usize, usize) {
+    (text_offset(bytes, start), text_offset(bytes, end))
+}
+
+fn text_offset(bytes: &[u8], at: usize) -> usize {
+    let mut raw = 0;
+    let mut text = 0;
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid().len();
+        if at <= raw + valid {
+            return text + at - raw;
+        }
+        raw += valid + chunk.invalid().len();
+        text += valid + REPLACEMENT_LEN * !chunk.invalid().is_empty() as usize;
+    }
+    text
+}
+
+// The number of characters in bytes, counting each byte that isn't part
+// of a valid character as one.
+pub fn char_count(bytes: &[u8]) -> usize {
+    bytes
+        .utf8_chunks()
+        .map(|chunk| chunk.valid().chars().count() + chunk.invalid().len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_offsets_around_invalid_bytes() {
+        // Decoded, this is "caf\u{fffd} x\u{fffd}", with the second
+        // replacement standing for a truncated three byte sequence.
+        let bytes = b"caf\xe9 x\xe2\x82";
+        let text = String::from_utf8_lossy(bytes);
+        assert_eq!("x", &text[7..8]);
+        assert_eq!(Some((5, 6)), to_bytes(bytes, (7, 8)));
+        assert_eq!((7, 8), to_text(bytes, (5, 6)));
+        // Nothing that takes in a replacement character maps back.
+        assert_eq!(None, to_bytes(bytes, (3, 6)));
+        assert_eq!(None, to_bytes(bytes, (7, 11)));
+        assert_eq!(Some((8, 8)), to_bytes(bytes, (11, 11)));
+        assert_eq!(8, char_count(bytes));
+    }
+}
@@ -1,6 +1,7 @@
 use std::env;
 use std::process;
 
+use minigrep::error::MinigrepError;
 use minigrep::Config;
 
 fn main() {
@@ -8,18 +9,45 @@ fn main() {
     // Rather than collecting the iterator values into a vector and passing them
     // in, we can pass ownership of the iterator returned from env::args() to
     // Config::build directly.
+    // The exit status is the same as grep's: 0 when something matched, 1
+    // when nothing did and 2 when something went wrong, so that scripts can
+    // tell "not found" apart from a mistake.
     let config = Config::build(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        match err {
+            MinigrepError::BadArguments(_) => {
+                eprintln!("Problem parsing arguments: {err}");
+                eprintln!("Try 'minigrep --help' for more information.");
+            }
+            _ => eprintln!("minigrep: {err}"),
+        }
+        process::exit(2);
     });
 
-    println!(
-        "Searching for {} in file {}",
-        config.query, config.file_path
-    );
-
-    if let Err(e) = minigrep::run(config) {
+    let result = minigrep::run(config);
+    if let Err(e) = &result {
         eprintln!("application error: {e}");
-        process::exit(1);
+    }
+    process::exit(exit_code(&result));
+}
+
+// The exit status for what run returned.
+fn exit_code(result: &Result<bool, MinigrepError>) -> i32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(_) => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exits_like_grep() {
+        assert_eq!(0, exit_code(&Ok(true)));
+        assert_eq!(1, exit_code(&Ok(false)));
+        let failed = vec![MinigrepError::BadArguments(String::from("oops"))];
+        assert_eq!(2, exit_code(&Err(MinigrepError::Incomplete { failed })));
     }
 }
@@ -0,0 +1,298 @@
+use std::cmp::Reverse;
+
+use crate::aho_corasick::AhoCorasick;
+use crate::fold;
+use crate::fuzzy::Fuzzy;
+use crate::literal::Finder;
+use crate::regex::{is_word_char, Regex, RegexError};
+use crate::Config;
+
+// Finds the matches for the patterns within a single line.
+// The search functions and the streaming Searcher both use a Matcher,
+// so they agree on what counts as a match however the text is read.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Literal(Finder),
+    // The query is stored already case folded.
+    CaseInsensitive(String),
+    Regex(Regex),
+    // Any number of literal patterns, searched for all at once.
+    Patterns(AhoCorasick),
+    // Several regular expressions, which are tried one after another.
+    Regexes(Vec<Regex>),
+    // Only the matches of the inner matcher that are whole words, as
+    // with -w: they mustn't have a letter, digit or '_' either side.
+    WholeWords(Box<Matcher>),
+    // Only matches of the inner matcher that cover the whole line, as
+    // with -x. Regexes get ^ and $ added instead, since the first match
+    // of a regex at the start of a line isn't always the longest.
+    WholeLine(Box<Matcher>),
+    // The closest match to the query in a line, allowing a few mistakes,
+    // as with --fuzzy.
+    Fuzzy(Fuzzy),
+}
+
+impl Matcher {
+    // Builds the right kind of matcher for the config.
+    // A bad regex is returned as an error rather than panicking.
+    pub fn new(config: &Config) -> Result<Matcher, RegexError> {
+        let regex = |pattern: &str| {
+            let pattern = match config.line_regexp {
+                true => format!("^(?:{pattern})$"),
+                false => pattern.to_string(),
+            };
+            match config.ignore_case {
+                true => Regex::new_case_insensitive(&pattern),
+                false => Regex::new(&pattern),
+            }
+        };
+        if let (Some(max_edits), [query]) = (config.fuzzy, config.patterns.as_slice()) {
+            return Ok(Matcher::Fuzzy(Fuzzy::new(
+                query,
+                max_edits,
+                config.ignore_case,
+            )));
+        }
+        let matcher = match (config.patterns.as_slice(), config.regex) {
+            ([pattern], true) => Matcher::Regex(regex(pattern)?),
+            ([pattern], false) if config.ignore_case => Matcher::case_insensitive(pattern),
+            ([pattern], false) => Matcher::literal(pattern),
+            (patterns, true) => Matcher::Regexes(
+                patterns
+                    .iter()
+                    .map(|pattern| regex(pattern))
+                    .collect::<Result<_, _>>()?,
+            ),
+            (patterns, false) => Matcher::Patterns(AhoCorasick::new(patterns, config.ignore_case)),
+        };
+        let matcher = if config.line_regexp && !config.regex {
+            Matcher::WholeLine(Box::new(matcher))
+        } else if config.word_regexp && !config.line_regexp {
+            Matcher::WholeWords(Box::new(matcher))
+        } else {
+            matcher
+        };
+        Ok(matcher)
+    }
+
+    pub fn literal(query: &str) -> Matcher {
+        Matcher::Literal(Finder::new(query))
+    }
+
+    pub fn case_insensitive(query: &str) -> Matcher {
+        Matcher::CaseInsensitive(fold::fold_str(query))
+    }
+
+    // Returns (start, end, pattern) for each of the non-overlapping matches
+    // in line, or an empty vector if there are none. start and end are
+    // byte offsets, and pattern is the index of the pattern that matched,
+    // which is always 0 when there is only one.
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize, usize)> {
+        let spans = match self {
+            Matcher::Literal(finder) => finder.find_all(line),
+            Matcher::CaseInsensitive(query) => fold::find_all(query, line),
+            Matcher::Regex(re) => re.find_iter(line).collect(),
+            Matcher::Patterns(ac) => return ac.find_all(line),
+            Matcher::Regexes(regexes) => return find_regexes(regexes, line),
+            Matcher::WholeWords(inner) => return find_words(inner, line),
+            Matcher::Fuzzy(fuzzy) => {
+                let found = fuzzy.find(line);
+                return found.map(|m| (m.start, m.end, 0)).into_iter().collect();
+            }
+            Matcher::WholeLine(inner) => {
+                // For the literal matchers, any match covering the whole
+                // line has to be the first one.
+                let mut found = inner.find_all(line);
+                found.truncate(1);
+                found.retain(|&(start, end, _)| start == 0 && end == line.len());
+                return found;
+            }
+        };
+        spans
+            .into_iter()
+            .map(|(start, end)| (start, end, 0))
+            .collect()
+    }
+
+    // Returns the first match in line that starts at or after at, if
+    // there is one.
+    pub fn find_at(&self, line: &str, at: usize) -> Option<(usize, usize, usize)> {
+        match self {
+            Matcher::Literal(finder) => {
+                let start = finder.find_at(line.as_bytes(), at)?;
+                Some((start, start + finder.needle().len(), 0))
+            }
+            Matcher::CaseInsensitive(query) if query.is_empty() => Some((at, at, 0)),
+            Matcher::CaseInsensitive(query) => {
+                fold::find_at(query, line, at).map(|(start, end)| (start, end, 0))
+            }
+            Matcher::Regex(re) => re.find_at(line, at).map(|(start, end)| (start, end, 0)),
+            Matcher::Regexes(regexes) => first_regex_match(regexes, line, at),
+            // The automaton finds all the matches in a line together, so
+            // it is given the rest of the line on its own.
+            Matcher::Patterns(ac) => {
+                let (start, end, id) = *ac.find_all(&line[at..]).first()?;
+                Some((at + start, at + end, id))
+            }
+            Matcher::Fuzzy(fuzzy) => {
+                let m = fuzzy.find(&line[at..])?;
+                Some((at + m.start, at + m.end, 0))
+            }
+            Matcher::WholeWords(_) | Matcher::WholeLine(_) => self
+                .find_all(line)
+                .into_iter()
+                .find(|&(start, _, _)| start >= at),
+        }
+    }
+
+    // Returns where each group of a regex matched, for a match that
+    // find_all found at span, with the whole match first. Anything other
+    // than a regex has no groups, so there is just the whole match.
+    pub fn captures(&self, line: &str, span: (usize, usize)) -> Vec<Option<(usize, usize)>> {
+        let whole = vec![Some(span)];
+        match self {
+            Matcher::Regex(re) => re.captures_at(line, span.0).unwrap_or(whole),
+            // Whichever regex found this match is the one whose groups
+            // are wanted.
+            Matcher::Regexes(regexes) => regexes
+                .iter()
+                .filter_map(|re| re.captures_at(line, span.0))
+                .find(|captures| captures[0] == Some(span))
+                .unwrap_or(whole),
+            Matcher::WholeWords(inner) | Matcher::WholeLine(inner) => inner.captures(line, span),
+            _ => whole,
+        }
+    }
+}
+
+// Finds the matches that are whole words. When a match isn't a whole
+// word, the search carries on from the character after where it started
+// rather than after where it ended, since a match overlapping it might be:
+// for "ab" in "aab ab", the second one.
+//
+// Several literal patterns are the exception. The automaton finds every
+// match in the line in one pass, overlapping ones included, so instead of
+// running it over the rest of the line again after each match that isn't
+// a whole word, which could take time proportional to the square of the
+// line's length, the next match is taken from that one pass. An empty
+// pattern would match at every place the search carries on from, so that
+// still goes the slow way.
+fn find_words(inner: &Matcher, line: &str) -> Vec<(usize, usize, usize)> {
+    let mut candidates = match inner {
+        Matcher::Patterns(ac) if !ac.has_empty() => Some(ac.find_overlapping(line).into_iter()),
+        _ => None,
+    };
+    let mut find_at = |at| match &mut candidates {
+        Some(candidates) => candidates.find(|&(start, _, _)| start >= at),
+        None => inner.find_at(line, at),
+    };
+    let mut found = Vec::new();
+    let mut at = 0;
+    while let Some((start, end, id)) = find_at(at) {
+        let before = line[..start].chars().next_back();
+        let after = line[end..].chars().next();
+        let whole = !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char);
+        if whole {
+            found.push((start, end, id));
+        }
+        // Step over a character after an empty match or one that wasn't
+        // a whole word, so the same match isn't found again.
+        at = match line[start..].chars().next() {
+            _ if whole && start < end => end,
+            Some(c) => start + c.len_utf8(),
+            None => break,
+        };
+    }
+    found
+}
+
+// Finds the matches of several regexes, picking whichever matches first
+// each time, like AhoCorasick::find_all does for literal patterns.
+fn find_regexes(regexes: &[Regex], line: &str) -> Vec<(usize, usize, usize)> {
+    let mut found = Vec::new();
+    let mut at = 0;
+    while let Some((start, end, id)) = first_regex_match(regexes, line, at) {
+        found.push((start, end, id));
+        at = if start < end {
+            end
+        } else {
+            // As in Regex::find_iter, step over a character after an
+            // empty match so it isn't found again.
+            match line[end..].chars().next() {
+                Some(c) => end + c.len_utf8(),
+                None => break,
+            }
+        };
+    }
+    found
+}
+
+// The match that starts first out of all the regexes, or the longest if
+// several start at the same place.
+fn first_regex_match(regexes: &[Regex], line: &str, at: usize) -> Option<(usize, usize, usize)> {
+    regexes
+        .iter()
+        .enumerate()
+        .filter_map(|(id, re)| re.find_at(line, at).map(|(start, end)| (start, end, id)))
+        .min_by_key(|&(start, end, id)| (start, Reverse(end), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        Matcher::new(&test_config(args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reports_which_pattern_matched() {
+        let m = matcher(&["-e", "cat", "-e", "dog", "-"]);
+        assert_eq!(vec![(0, 3, 1), (8, 11, 0)], m.find_all("dog and cat"));
+
+        let m = matcher(&["-E", "-e", r"\d+", "-e", "[a-z]+", "-"]);
+        assert_eq!(vec![(0, 3, 1), (3, 5, 0)], m.find_all("abc12"));
+    }
+
+    #[test]
+    fn whole_words() {
+        let m = matcher(&["-w", "id", "-"]);
+        assert!(m.find_all("valid width").is_empty());
+        assert_eq!(vec![(5, 7, 0)], m.find_all("user id=3"));
+        // The first "ab" fails, but one overlapping it is a whole word.
+        let m = matcher(&["-w", "ab", "-"]);
+        assert_eq!(vec![(4, 6, 0)], m.find_all("aab ab"));
+        // Word characters aren't only ASCII, and case folding still works.
+        let m = matcher(&["-wi", "strasse", "-"]);
+        assert_eq!(vec![(0, 7, 0)], m.find_all("Straße."));
+        assert!(m.find_all("Straßenbahn").is_empty());
+        let m = matcher(&["-wE", "-e", "a+", "-e", "b", "-"]);
+        assert_eq!(vec![(0, 2, 0), (6, 7, 1)], m.find_all("aa ab b"));
+        // With several literal patterns, a whole word overlapping one that
+        // isn't is still found.
+        let m = matcher(&["-w", "-e", "xa b", "-e", "a", "-e", "b", "-"]);
+        assert_eq!(vec![(4, 5, 2)], m.find_all("xxa b"));
+        let m = matcher(&["-wi", "-e", "AB", "-e", "cd", "-"]);
+        assert_eq!(vec![(4, 6, 0), (7, 9, 1)], m.find_all("aab ab CD"));
+    }
+
+    #[test]
+    fn whole_lines() {
+        // The regex finds "a" first, but "ab" is the whole line.
+        for args in [
+            &["-x", "ab", "-"][..],
+            &["-xi", "AB", "-"],
+            &["-xE", "a|ab", "-"],
+            &["-x", "-e", "a", "-e", "ab", "-"],
+        ] {
+            let m = matcher(args);
+            assert_eq!(
+                Some((0, 2)),
+                m.find_at("ab", 0).map(|(s, e, _)| (s, e)),
+                "{args:?}"
+            );
+            assert!(m.find_all("abc").is_empty(), "{args:?}");
+        }
+    }
+}
@@ -0,0 +1,612 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use crate::context::Line;
+use crate::encoding;
+use crate::error::MinigrepError;
+use crate::json::{write_summary, JsonPrinter, Stats};
+use crate::printer::{write_group_separator, Printer};
+use crate::replace;
+use crate::searcher::{self, Searcher};
+use crate::walk::Walk;
+use crate::{BinaryFiles, Config, Input, Match, Output};
+
+// Searching many files at once.
+//
+// One thread walks the inputs and numbers each file it finds. A pool of
+// worker threads takes files off a queue, searches them and sends their
+// output, in chunks, to the collector on the calling thread. The collector
+// writes out one file at a time: normally the next file by number, so the
+// output is the same as if the files had been searched one after another,
+// or with --unordered, whichever file has output ready first.
+//
+// The file currently being written out is streamed as its chunks arrive,
+// so searching a single huge file doesn't need all its output in memory.
+// Output from other files is held back until it is their turn.
+
+// Workers pass on their output once they have about this much buffered.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct Job {
+    index: usize,
+    input: Input,
+    // The name shown for this input, in file name prefixes and errors.
+    name: String,
+}
+
+enum Message {
+    Output(usize, Vec<u8>),
+    // A file has been searched, with the number of matching lines, or
+    // what went wrong if that failed.
+    Done(usize, Result<usize, MinigrepError>),
+}
+
+// Searches every file in inputs and writes the results to out.
+// Returns the totals for the search, including the files that couldn't be
+// searched; the reasons are also printed to stderr as they come up.
+pub(crate) fn search_all<W: Write>(
+    config: &Config,
+    searcher: &Searcher,
+    inputs: Vec<Input>,
+    with_filename: bool,
+    out: W,
+) -> io::Result<Stats> {
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let (job_sender, job_receiver) = mpsc::channel();
+    let job_receiver = Mutex::new(job_receiver);
+    let (sender, receiver) = mpsc::channel();
+    // Set when nobody wants any more output, to stop the other threads early.
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let walk_sender = sender.clone();
+        let stop = &stop;
+        scope.spawn(move || walk_inputs(config, inputs, job_sender, walk_sender, stop));
+
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let job_receiver = &job_receiver;
+            scope.spawn(move || loop {
+                // The lock is only held while waiting for the next job.
+                let job = match job_receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = search_job(config, searcher, &job, with_filename, &sender, stop);
+                if sender.send(Message::Done(job.index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        // The collector knows every file is finished once all the other
+        // threads have dropped their senders, so we drop ours too.
+        drop(sender);
+
+        let mut collector = Collector::new(config, out);
+        let result = collector.collect(receiver);
+        if result.is_err() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        result.map(|_| collector.stats)
+    })
+}
+
+// Walks each input, sending the files found to the workers as jobs.
+// Problems found while walking take up a number in the output order just
+// like a file does, so that they are reported in a consistent place.
+fn walk_inputs(
+    config: &Config,
+    inputs: Vec<Input>,
+    jobs: Sender<Job>,
+    results: Sender<Message>,
+    stop: &AtomicBool,
+) {
+    // If the other end of a channel has gone, the collector has given up
+    // and there's no point carrying on.
+    let mut index = 0;
+    for input in inputs {
+        let path = match input {
+            Input::Stdin => {
+                let job = Job {
+                    index,
+                    input: Input::Stdin,
+                    name: config.label.clone(),
+                };
+                if jobs.send(job).is_err() {
+                    return;
+                }
+                index += 1;
+                continue;
+            }
+            Input::Path(path) => path,
+        };
+        for file_path in Walk::new(&path, config.walk.clone()) {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let sent = match file_path {
+                Ok(file_path) => jobs
+                    .send(Job {
+                        index,
+                        name: file_path.to_string_lossy().into_owned(),
+                        input: Input::Path(file_path),
+                    })
+                    .is_ok(),
+                Err(e) => results.send(Message::Done(index, Err(e))).is_ok(),
+            };
+            if !sent {
+                return;
+            }
+            index += 1;
+        }
+    }
+}
+
+fn search_job(
+    config: &Config,
+    searcher: &Searcher,
+    job: &Job,
+    with_filename: bool,
+    sender: &Sender<Message>,
+    stop: &AtomicBool,
+) -> Result<usize, MinigrepError> {
+    let name = with_filename.then_some(job.name.as_str());
+    let mut printer = Printer::new(config, Vec::new());
+    if config.in_place {
+        return edit_job(config, searcher, job, printer, sender);
+    }
+    let path = match &job.input {
+        Input::Stdin => job.name.as_bytes(),
+        Input::Path(path) => path.as_os_str().as_encoded_bytes(),
+    };
+    let mut json = config.json.then(|| JsonPrinter::new(config, path));
+    // Standard input might be a program that prints slowly, like
+    // `tail -f`, so its output is passed on a line at a time rather
+    // than waiting for a chunk to fill up.
+    let chunk_size = match job.input {
+        Input::Stdin => 1,
+        Input::Path(_) => CHUNK_SIZE,
+    };
+    let send = |buffer: &mut Vec<u8>| -> io::Result<()> {
+        if stop.load(Ordering::Relaxed) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        sender
+            .send(Message::Output(job.index, mem::take(buffer)))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    };
+    let mut sink = |line: Line| -> io::Result<()> {
+        if config.output != Output::Lines {
+            return Ok(());
+        }
+        // With --replace, matching lines are printed with the replacements
+        // made, and it's the replacements that get highlighted.
+        let replaced;
+        let line = match (&config.replace, line) {
+            (Some(replacement), Line::Match(m)) => {
+                let spans: Vec<_> = m.spans.iter().map(|&span| m.line_span(span)).collect();
+                let (text, spans) = replacement.apply(searcher.matcher(), m.line, &spans);
+                replaced = text;
+                Line::Match(Match {
+                    line: &replaced,
+                    spans,
+                    raw: None,
+                    ..m
+                })
+            }
+            (_, line) => line,
+        };
+        match &mut json {
+            Some(json) => json.print(printer.out(), &line)?,
+            None => printer.print(name, &line)?,
+        }
+        if printer.out().len() >= chunk_size {
+            send(printer.out())?;
+        }
+        Ok(())
+    };
+
+    let result = match &job.input {
+        Input::Stdin => search_input(config, searcher, io::stdin().lock(), &mut sink),
+        Input::Path(path) => File::open(path)
+            .and_then(|file| search_input(config, searcher, BufReader::new(file), &mut sink)),
+    };
+    let result = result.and_then(|(count, binary)| {
+        if let Some(json) = &mut json {
+            json.finish(printer.out(), count)?;
+        }
+        match config.output {
+            Output::Lines if binary && count > 0 && !config.json => {
+                printer.print_binary_match(&job.name)?
+            }
+            Output::Lines => {}
+            Output::Count => printer.print_count(name, count)?,
+            Output::FilesWithMatches if count > 0 => printer.print_name(&job.name)?,
+            Output::FilesWithoutMatches if count == 0 => printer.print_name(&job.name)?,
+            Output::FilesWithMatches | Output::FilesWithoutMatches => {}
+        }
+        send(printer.out())?;
+        Ok(count)
+    });
+    result.map_err(|source| MinigrepError::Io {
+        path: PathBuf::from(&job.name),
+        source,
+    })
+}
+
+// Searches reader, passing lines to sink, unless it turns out to be a
+// binary file. Returns the number of matching lines and whether the file
+// was binary. Text in other encodings is converted to UTF-8 first, so that
+// UTF-16, which is full of NUL bytes, isn't taken for binary.
+fn search_input(
+    config: &Config,
+    searcher: &Searcher,
+    reader: impl BufRead,
+    sink: impl FnMut(Line) -> io::Result<()>,
+) -> io::Result<(usize, bool)> {
+    let mut reader = encoding::decode(reader, config.encoding)?;
+    if config.binary_files == BinaryFiles::Text || !searcher::is_binary(&mut reader)? {
+        return searcher
+            .search_reader(reader, sink)
+            .map(|count| (count, false));
+    }
+    let count = match config.binary_files {
+        BinaryFiles::WithoutMatch => 0,
+        _ => searcher
+            .for_binary(config.output)
+            .search_reader(reader, |_| Ok(()))?,
+    };
+    Ok((count, true))
+}
+
+// Makes the replacements in a file for --in-place, printing how many
+// there were if there were any.
+fn edit_job(
+    config: &Config,
+    searcher: &Searcher,
+    job: &Job,
+    mut printer: Printer<Vec<u8>>,
+    sender: &Sender<Message>,
+) -> Result<usize, MinigrepError> {
+    let (Input::Path(path), Some(replacement)) = (&job.input, &config.replace) else {
+        return Err(MinigrepError::Io {
+            path: PathBuf::from(&job.name),
+            source: io::Error::other("can't be edited in place"),
+        });
+    };
+    let mut edit = || -> io::Result<usize> {
+        // Binary files are left alone, since rewriting them a line at a
+        // time would most likely break them.
+        if config.binary_files != BinaryFiles::Text
+            && searcher::is_binary(&mut BufReader::new(File::open(path)?))?
+        {
+            return Ok(0);
+        }
+        let backup = config.backup.as_deref();
+        let replaced = replace::rewrite_file(path, searcher.matcher(), replacement, backup)?;
+        if replaced > 0 {
+            printer.print_replacements(&job.name, replaced)?;
+            sender
+                .send(Message::Output(job.index, mem::take(printer.out())))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(replaced)
+    };
+    edit().map_err(|e| MinigrepError::reading_text(&job.name, e))
+}
+
+#[derive(Default)]
+struct Pending {
+    chunks: Vec<Vec<u8>>,
+    done: Option<Result<usize, MinigrepError>>,
+}
+
+// Puts the output from the workers back together, a whole file at a time.
+struct Collector<'c, W: Write> {
+    config: &'c Config,
+    out: W,
+    // Output and results waiting to be written, by file number.
+    pending: BTreeMap<usize, Pending>,
+    // The file being written out, which has the output to itself until
+    // it is finished.
+    current: Option<usize>,
+    // In order, the number of the next file to write.
+    next: usize,
+    // Whether current has written anything yet.
+    started: bool,
+    printed: bool,
+    stats: Stats,
+    started_at: Instant,
+}
+
+impl<'c, W: Write> Collector<'c, W> {
+    fn new(config: &'c Config, out: W) -> Collector<'c, W> {
+        Collector {
+            config,
+            out,
+            pending: BTreeMap::new(),
+            current: None,
+            next: 0,
+            started: false,
+            printed: false,
+            stats: Stats::default(),
+            started_at: Instant::now(),
+        }
+    }
+
+    fn collect(&mut self, receiver: Receiver<Message>) -> io::Result<()> {
+        for message in receiver {
+            match message {
+                Message::Output(index, chunk) => {
+                    self.pending.entry(index).or_default().chunks.push(chunk);
+                }
+                Message::Done(index, result) => {
+                    self.pending.entry(index).or_default().done = Some(result);
+                }
+            }
+            self.write_ready()?;
+        }
+        if self.config.json {
+            write_summary(&mut self.out, &self.stats, self.started_at.elapsed())?;
+        }
+        self.out.flush()
+    }
+
+    // Writes out as much as can be written without breaking the order.
+    fn write_ready(&mut self) -> io::Result<()> {
+        loop {
+            let index = match self.current.or_else(|| self.choose_next()) {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+            self.current = Some(index);
+            let pending = match self.pending.get_mut(&index) {
+                Some(pending) => pending,
+                None => return Ok(()),
+            };
+
+            let chunks = mem::take(&mut pending.chunks);
+            let done = pending.done.take();
+            for chunk in chunks {
+                if !self.started && self.printed && self.separates_files() {
+                    write_group_separator(&mut self.out, self.config)?;
+                }
+                self.started = true;
+                self.printed = true;
+                self.out.write_all(&chunk)?;
+            }
+
+            let result = match done {
+                Some(result) => result,
+                None => {
+                    // The file isn't finished yet. Output written part way
+                    // through a file still needs to get to the terminal.
+                    self.out.flush()?;
+                    return Ok(());
+                }
+            };
+            match result {
+                Ok(matched_lines) => {
+                    self.stats.searches += 1;
+                    self.stats.searches_with_match += (matched_lines > 0) as usize;
+                    self.stats.matched_lines += matched_lines;
+                }
+                Err(e) => {
+                    self.out.flush()?;
+                    eprintln!("minigrep: {e}");
+                    self.stats.errors.push(e);
+                }
+            }
+            self.pending.remove(&index);
+            self.current = None;
+            self.started = false;
+            self.next = index + 1;
+        }
+    }
+
+    // With context, the output of different files is separated by "--"
+    // like the groups of lines within a file are. There's no place for it
+    // in JSON Lines, where every line is an event, and no context in the
+    // counts, file names or summaries of edits printed instead of lines.
+    fn separates_files(&self) -> bool {
+        self.config.has_context()
+            && !self.config.json
+            && !self.config.in_place
+            && self.config.output == Output::Lines
+    }
+
+    fn choose_next(&self) -> Option<usize> {
+        if !self.config.unordered {
+            return Some(self.next);
+        }
+        // Finished files can be written out in one go, so they are
+        // preferred over ones that might keep everything else waiting.
+        let finished = self.pending.iter().find(|(_, p)| p.done.is_some());
+        let started = self.pending.iter().find(|(_, p)| !p.chunks.is_empty());
+        finished.or(started).map(|(&index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::{test_config, TempDir};
+    use std::fs;
+
+    #[test]
+    fn output_is_in_file_order() {
+        let root = TempDir::new("parallel");
+        let mut inputs = Vec::new();
+        let mut expected = String::new();
+        for i in 0..50 {
+            let path = root.join(format!("{i:02}.txt"));
+            // Make the files different sizes so they finish out of order.
+            let contents = format!("match {i}\n").repeat(1 + (i * 37) % 200);
+            fs::write(&path, &contents).unwrap();
+            for line in contents.lines() {
+                expected.push_str(&format!("{}:{line}\n", path.display()));
+            }
+            inputs.push(Input::Path(path));
+        }
+        inputs.push(Input::Path(root.join("missing.txt")));
+
+        let config = test_config(&["--threads", "8", "match"]).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+        let searcher = Searcher::new(&matcher, 0, 0);
+        let mut out = Vec::new();
+        let stats = search_all(&config, &searcher, inputs, true, &mut out).unwrap();
+
+        assert_eq!(
+            (50, 50, 1),
+            (
+                stats.searches,
+                stats.searches_with_match,
+                stats.errors.len()
+            )
+        );
+        // The error is kept as it is, for library users to look at.
+        assert!(matches!(
+            &stats.errors[0],
+            MinigrepError::Io { path, source }
+                if *path == root.join("missing.txt") && source.kind() == io::ErrorKind::NotFound
+        ));
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    // Searches files with the given names and contents as config says,
+    // returning what was printed. The files go in a directory named after
+    // the test, since tests run at the same time.
+    fn search_files(test: &str, config: &Config, files: &[(&str, &str)]) -> String {
+        let root = TempDir::new(test);
+        let mut inputs = Vec::new();
+        for (name, contents) in files {
+            fs::write(root.join(name), contents).unwrap();
+            inputs.push(Input::Path(root.join(name)));
+        }
+        let matcher = Matcher::new(config).unwrap();
+        let searcher = Searcher::for_config(&matcher, config);
+        let mut out = Vec::new();
+        search_all(config, &searcher, inputs, true, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_has_no_separators() {
+        let config = test_config(&["--json", "-C1", "match"]).unwrap();
+        let out = search_files(
+            "json-separators",
+            &config,
+            &[("a.txt", "x\nmatch\n"), ("b.txt", "match\ny\n")],
+        );
+        // A begin, context, match and end for each file, and the summary.
+        assert_eq!(9, out.lines().count());
+        assert!(out.lines().all(|line| line.starts_with('{')), "{out}");
+    }
+
+    #[test]
+    fn counts_and_names_have_no_separators() {
+        let files = [("a.txt", "x\nmatch\n"), ("b.txt", "match\ny\n")];
+        for (args, expected) in [
+            (&["-c", "-C1", "match"][..], "a.txt:1\nb.txt:1\n"),
+            (&["-l", "-C1", "match"], "a.txt\nb.txt\n"),
+            (&["-L", "-C1", "nothing"], "a.txt\nb.txt\n"),
+        ] {
+            let out = search_files("count-separators", &test_config(args).unwrap(), &files);
+            // The names are printed in full, from the temporary directory.
+            let names: Vec<_> = out
+                .lines()
+                .map(|line| line.rsplit('/').next().unwrap())
+                .collect();
+            assert_eq!(expected, names.join("\n") + "\n", "{args:?}");
+        }
+    }
+
+    #[test]
+    fn edits_have_no_separators() {
+        // --in-place needs a file path, though the files searched here are
+        // the ones made by search_files.
+        let config =
+            test_config(&["--in-place", "--replace", "X", "-C1", "match", "e1.txt"]).unwrap();
+        let files = [("e1.txt", "x\nmatch\n"), ("e2.txt", "match match\n")];
+        let out = search_files("edit-separators", &config, &files);
+        let summaries: Vec<_> = out
+            .lines()
+            .map(|line| line.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(
+            vec!["e1.txt: 1 replacement", "e2.txt: 2 replacements"],
+            summaries
+        );
+    }
+
+    #[test]
+    fn stdin_is_named_by_its_label() {
+        let config = test_config(&["--label", "pipe", "match"]).unwrap();
+        let (jobs, job_receiver) = mpsc::channel();
+        let (results, _) = mpsc::channel();
+        let root = TempDir::new("stdin-label");
+        let file = root.join("poem.txt");
+        fs::write(&file, "match\n").unwrap();
+        let inputs = vec![Input::Stdin, Input::Path(file.clone())];
+        walk_inputs(&config, inputs, jobs, results, &AtomicBool::new(false));
+        let names: Vec<_> = job_receiver.iter().map(|job| job.name).collect();
+        assert_eq!(
+            vec![String::from("pipe"), file.display().to_string()],
+            names
+        );
+
+        // The name is what goes in front of each line printed from it.
+        let matcher = Matcher::new(&config).unwrap();
+        let mut printer = Printer::new(&config, Vec::new());
+        Searcher::new(&matcher, 0, 0)
+            .search_reader(&b"a match\n"[..], |line| {
+                printer.print(Some(&names[0]), &line)
+            })
+            .unwrap();
+        assert_eq!(b"pipe:a match\n", &printer.out()[..]);
+    }
+
+    #[test]
+    fn unordered_output_keeps_files_together() {
+        let config = test_config(&["--unordered", "-C1", "match"]).unwrap();
+        let mut out = Vec::new();
+        let mut collector = Collector::new(&config, &mut out);
+        let (sender, receiver) = mpsc::channel();
+        sender
+            .send(Message::Output(1, b"b:match\n".to_vec()))
+            .unwrap();
+        sender
+            .send(Message::Output(0, b"a:match\n".to_vec()))
+            .unwrap();
+        sender
+            .send(Message::Output(1, b"b:match\n".to_vec()))
+            .unwrap();
+        sender.send(Message::Done(0, Ok(1))).unwrap();
+        sender.send(Message::Done(1, Ok(2))).unwrap();
+        drop(sender);
+        collector.collect(receiver).unwrap();
+
+        assert_eq!(
+            "b:match\nb:match\n--\na:match\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::context::Line;
+use crate::{Config, Match};
+
+// Writes the lines from one file out in the format the config asks for.
+// Separating the output of different files is up to the caller.
+pub(crate) struct Printer<'c, W: Write> {
+    config: &'c Config,
+    out: W,
+    // The number of the last line printed.
+    last_line: Option<usize>,
+}
+
+impl<'c, W: Write> Printer<'c, W> {
+    pub fn new(config: &'c Config, out: W) -> Printer<'c, W> {
+        Printer {
+            config,
+            out,
+            last_line: None,
+        }
+    }
+
+    // Gives access to what is being written to, for example to pass on
+    // the output so far when writing into a buffer.
+    pub fn out(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    // Prints the number of matching lines in a file, for -c.
+    pub fn print_count(&mut self, name: Option<&str>, count: usize) -> io::Result<()> {
+        if let Some(name) = name {
+            self.paint(FILE_NAME, name)?;
+            self.paint(SEPARATOR, ':')?;
+        }
+        writeln!(self.out, "{count}")
+    }
+
+    // Prints just the name of a file, for -l and -L.
+    pub fn print_name(&mut self, name: &str) -> io::Result<()> {
+        self.paint(FILE_NAME, name)?;
+        writeln!(self.out)
+    }
+
+    // Prints the note that stands in for the lines of a binary file.
+    pub fn print_binary_match(&mut self, name: &str) -> io::Result<()> {
+        write!(self.out, "Binary file ")?;
+        self.paint(FILE_NAME, name)?;
+        writeln!(self.out, " matches")
+    }
+
+    // Prints how many matches were replaced in a file, for --in-place.
+    pub fn print_replacements(&mut self, name: &str, count: usize) -> io::Result<()> {
+        self.paint(FILE_NAME, name)?;
+        self.paint(SEPARATOR, ':')?;
+        let plural = if count == 1 { "" } else { "s" };
+        writeln!(self.out, " {count} replacement{plural}")
+    }
+
+    // Prints a line, prefixed with the name of the file it came from if
+    // one is given, and any extra details asked for. Like grep, the prefixes
+    // of matching lines end in ':' and those of context lines end in '-'.
+    pub fn print(&mut self, name: Option<&str>, line: &Line) -> io::Result<()> {
+        // When printing context, groups of lines that don't follow on from
+        // each other are separated by "--".
+        if self.config.has_context() {
+            if let Some(last) = self.last_line {
+                if last + 1 != line.line_number() {
+                    write_group_separator(&mut self.out, self.config)?;
+                }
+            }
+        }
+        self.last_line = Some(line.line_number());
+
+        let (text, byte_offset, column, separator) = match line {
+            Line::Match(m) => (m.line, m.byte_offset, Some(m.column), ':'),
+            Line::Context {
+                line, byte_offset, ..
+            } => (*line, *byte_offset, None, '-'),
+        };
+
+        if let Some(name) = name {
+            self.paint(FILE_NAME, name)?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        if self.config.line_number {
+            self.paint(NUMBER, line.line_number())?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        if let (true, Some(column)) = (self.config.column, column) {
+            self.paint(NUMBER, column)?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        if self.config.byte_offset {
+            self.paint(NUMBER, byte_offset)?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        // With --fuzzy, how many edits away from the query the match was.
+        if let Line::Match(Match {
+            distance: Some(distance),
+            ..
+        }) = line
+        {
+            self.paint(NUMBER, format!("~{distance}"))?;
+            self.paint(SEPARATOR, separator)?;
+        }
+        if let (true, Line::Match(m)) = (self.config.show_pattern, line) {
+            let patterns: Vec<&str> = m
+                .patterns
+                .iter()
+                .map(|&i| self.config.patterns[i].as_str())
+                .collect();
+            write!(self.out, "{}", patterns.join(","))?;
+            self.paint(SEPARATOR, separator)?;
+        }
+
+        // The matches in the line are highlighted, and the text between
+        // them is written as it is.
+        let spans: Vec<(usize, usize)> = match line {
+            Line::Match(m) if self.config.color => {
+                m.spans.iter().map(|&span| m.line_span(span)).collect()
+            }
+            _ => Vec::new(),
+        };
+        let mut written = 0;
+        for (start, end) in spans {
+            write!(self.out, "{}", &text[written..start])?;
+            self.paint(MATCH, &text[start..end])?;
+            written = end;
+        }
+        writeln!(self.out, "{}", &text[written..])
+    }
+
+    // Writes text in the given color, if colors are turned on.
+    fn paint(&mut self, color: &str, text: impl Display) -> io::Result<()> {
+        paint(&mut self.out, self.config, color, text)
+    }
+}
+
+// The colors used for each part of the output, as ANSI escape codes. They
+// are the same as grep's: bold red for matches, magenta for file names,
+// green for numbers and cyan for the separators between them.
+const MATCH: &str = "1;31";
+const FILE_NAME: &str = "35";
+const NUMBER: &str = "32";
+const SEPARATOR: &str = "36";
+
+fn paint(out: &mut impl Write, config: &Config, color: &str, text: impl Display) -> io::Result<()> {
+    if config.color {
+        // "\x1b[" starts an escape code, and the code 0 puts the color
+        // back to normal afterwards.
+        write!(out, "\x1b[{color}m{text}\x1b[0m")
+    } else {
+        write!(out, "{text}")
+    }
+}
+
+// Writes the "--" that goes between groups of lines when context is printed.
+pub(crate) fn write_group_separator(out: &mut impl Write, config: &Config) -> io::Result<()> {
+    paint(out, config, SEPARATOR, "--")?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::searcher::Searcher;
+    use crate::test_config;
+
+    #[test]
+    fn prefixes_and_separators() {
+        let config = test_config(&["-n", "-C1", "match", "file"]).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+        let searcher = Searcher::new(&matcher, 1, 1);
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&config, &mut out);
+
+        let contents = "match\na\nb\nc\nmatch\n";
+        searcher
+            .search_reader(contents.as_bytes(), |line| {
+                printer.print(Some("one.txt"), &line)
+            })
+            .unwrap();
+
+        assert_eq!(
+            "one.txt:1:match\n\
+             one.txt-2-a\n\
+             --\n\
+             one.txt-4-c\n\
+             one.txt:5:match\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let config = test_config(&["-n", "--color=always", "b", "file"]).unwrap();
+        let matcher = Matcher::new(&config).unwrap();
+        let searcher = Searcher::new(&matcher, 0, 0);
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&config, &mut out);
+        searcher
+            .search_reader("abcb\n".as_bytes(), |line| printer.print(Some("f"), &line))
+            .unwrap();
+
+        assert_eq!(
+            "\x1b[35mf\x1b[0m\x1b[36m:\x1b[0m\x1b[32m1\x1b[0m\x1b[36m:\x1b[0m\
+             a\x1b[1;31mb\x1b[0mc\x1b[1;31mb\x1b[0m\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,954 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::fold::fold;
+
+// A small regular expression engine for minigrep.
+// Patterns are parsed into a syntax tree, then compiled into a list of
+// instructions for a Pike VM. The VM runs every possible path through the
+// pattern in lock-step, so matching takes time proportional to the length
+// of the text times the size of the program and never backtracks
+// exponentially, which matters when searching large logs.
+//
+// Supported syntax:
+//   literals, `.`, `^`, `$`, `\b`, `\B`
+//   character classes `[abc]`, `[^a-z]`, `\d \D \w \W \s \S`
+//   groups `(...)`, non-capturing groups `(?:...)`, alternation `a|b`
+//   repetition `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}` and lazy forms `*?` etc.
+
+// Counted repetition is expanded into copies of the repeated expression,
+// so we put an upper bound on it to stop tiny patterns compiling into
+// enormous programs.
+const MAX_REPEAT: u32 = 1000;
+// Nested repetitions multiply, so ((a{1000}){1000}){1000} is within
+// MAX_REPEAT everywhere but would still be a billion instructions. The
+// size of the whole program is checked before compiling it as well.
+const MAX_PROGRAM: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexError {
+    pattern: String,
+    position: usize,
+    message: String,
+}
+
+impl RegexError {
+    fn new(pattern: &str, position: usize, message: &str) -> RegexError {
+        RegexError {
+            pattern: pattern.to_string(),
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    // The character position in the pattern where the problem was found.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid regex '{}': {} at position {}",
+            self.pattern, self.message, self.position
+        )
+    }
+}
+
+impl Error for RegexError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit(negated) => c.is_ascii_digit() != negated,
+            ClassItem::Word(negated) => is_word_char(c) != negated,
+            ClassItem::Space(negated) => c.is_whitespace() != negated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char, case_insensitive: bool) -> bool {
+        let found = if case_insensitive {
+            // Try the character as written and in both cases, so that
+            // [a-z] also matches 'Q' and [A-Z] also matches 'q'.
+            c.to_lowercase()
+                .chain(c.to_uppercase())
+                .chain(std::iter::once(c))
+                .any(|c| self.items.iter().any(|item| item.matches(c)))
+        } else {
+            self.items.iter().any(|item| item.matches(c))
+        };
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pattern: &'a str) -> Parser<'a> {
+        Parser {
+            pattern,
+            chars: pattern.chars().collect(),
+            pos: 0,
+            // Group 0 is always the whole match.
+            groups: 1,
+        }
+    }
+
+    fn error(&self, position: usize, message: &str) -> RegexError {
+        RegexError::new(self.pattern, position, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn parse(mut self) -> Result<(Node, usize), RegexError> {
+        let node = self.parse_alternation()?;
+        match self.peek() {
+            None => Ok((node, self.groups)),
+            Some(')') => Err(self.error(self.pos, "unmatched ')'")),
+            Some(_) => Err(self.error(self.pos, "unexpected character")),
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            nodes.push(self.parse_repeat()?);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, RegexError> {
+        let mut node = self.parse_atom()?;
+        loop {
+            let quantifier_pos = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    (0, None)
+                }
+                Some('+') => {
+                    self.pos += 1;
+                    (1, None)
+                }
+                Some('?') => {
+                    self.pos += 1;
+                    (0, Some(1))
+                }
+                Some('{') => match self.parse_counted()? {
+                    Some(bounds) => bounds,
+                    None => break,
+                },
+                _ => break,
+            };
+            if let Node::Assert(_) | Node::Empty = node {
+                return Err(self.error(quantifier_pos, "nothing to repeat"));
+            }
+            if let Node::Repeat { .. } = node {
+                // Patterns like `a**` are almost certainly a mistake.
+                return Err(self.error(quantifier_pos, "repeated quantifier"));
+            }
+            let greedy = if self.peek() == Some('?') {
+                self.pos += 1;
+                false
+            } else {
+                true
+            };
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+                greedy,
+            };
+        }
+        Ok(node)
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`. If what follows the brace isn't a
+    // well formed count the brace is treated as a literal character, the
+    // same way most grep implementations behave.
+    fn parse_counted(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let open = self.pos;
+        let mut end = open + 1;
+        while end < self.chars.len() && self.chars[end] != '}' {
+            end += 1;
+        }
+        if end >= self.chars.len() {
+            return Ok(None);
+        }
+        let inside: String = self.chars[open + 1..end].iter().collect();
+        let parse_number = |s: &str| -> Option<u32> {
+            if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+                s.parse().ok()
+            } else {
+                None
+            }
+        };
+        let bounds = match inside.split_once(',') {
+            None => parse_number(&inside).map(|n| (n, Some(n))),
+            Some((lo, "")) => parse_number(lo).map(|n| (n, None)),
+            Some((lo, hi)) => match (parse_number(lo), parse_number(hi)) {
+                (Some(lo), Some(hi)) => Some((lo, Some(hi))),
+                _ => None,
+            },
+        };
+        let (min, max) = match bounds {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        };
+        if let Some(max) = max {
+            if max < min {
+                return Err(self.error(open, "repetition range is out of order"));
+            }
+        }
+        if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(self.error(open, "repetition count is too large"));
+        }
+        self.pos = end + 1;
+        Ok(Some((min, max)))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(start, "unexpected end of pattern")),
+        };
+        self.pos += 1;
+        match c {
+            '(' => self.parse_group(start),
+            '[' => self.parse_class(start),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Assert(Assertion::StartLine)),
+            '$' => Ok(Node::Assert(Assertion::EndLine)),
+            '*' | '+' | '?' => Err(self.error(start, "nothing to repeat")),
+            '\\' => self.parse_escape(start),
+            c => Ok(Node::Literal(c)),
+        }
+    }
+
+    fn parse_group(&mut self, start: usize) -> Result<Node, RegexError> {
+        let index = if self.chars[self.pos..].starts_with(&['?', ':']) {
+            self.pos += 2;
+            None
+        } else if self.peek() == Some('?') {
+            return Err(self.error(self.pos, "unsupported group flag"));
+        } else {
+            self.groups += 1;
+            Some(self.groups - 1)
+        };
+        let node = self.parse_alternation()?;
+        if self.peek() != Some(')') {
+            return Err(self.error(start, "unclosed group"));
+        }
+        self.pos += 1;
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_escape(&mut self, start: usize) -> Result<Node, RegexError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(start, "trailing backslash")),
+        };
+        self.pos += 1;
+        let node = match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            _ => match self.escape_class_item(c) {
+                Some(item) => Node::Class(Class {
+                    items: vec![item],
+                    negated: false,
+                }),
+                None => Node::Literal(self.escape_literal(start, c)?),
+            },
+        };
+        Ok(node)
+    }
+
+    fn escape_class_item(&self, c: char) -> Option<ClassItem> {
+        match c {
+            'd' => Some(ClassItem::Digit(false)),
+            'D' => Some(ClassItem::Digit(true)),
+            'w' => Some(ClassItem::Word(false)),
+            'W' => Some(ClassItem::Word(true)),
+            's' => Some(ClassItem::Space(false)),
+            'S' => Some(ClassItem::Space(true)),
+            _ => None,
+        }
+    }
+
+    fn escape_literal(&self, start: usize, c: char) -> Result<char, RegexError> {
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            // Escaping letters and digits that have no meaning is an error,
+            // so that they stay free for future use.
+            c if c.is_alphanumeric() => Err(self.error(start, "unknown escape sequence")),
+            c => Ok(c),
+        }
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Node, RegexError> {
+        let mut class = Class {
+            items: Vec::new(),
+            negated: false,
+        };
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            class.negated = true;
+        }
+        let mut first = true;
+        loop {
+            let item_pos = self.pos;
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error(start, "unclosed character class")),
+            };
+            self.pos += 1;
+            // A ']' straight after the opening bracket is a literal.
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let escaped = match self.peek() {
+                    Some(e) => e,
+                    None => return Err(self.error(start, "unclosed character class")),
+                };
+                self.pos += 1;
+                if let Some(item) = self.escape_class_item(escaped) {
+                    class.items.push(item);
+                    continue;
+                }
+                self.escape_literal(item_pos, escaped)?
+            } else {
+                c
+            };
+
+            // A '-' forms a range unless it is the last thing in the class.
+            let is_range = self.peek() == Some('-')
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|&next| next != ']');
+            if !is_range {
+                class.items.push(ClassItem::Range(lo, lo));
+                continue;
+            }
+            self.pos += 1;
+            let hi_pos = self.pos;
+            let mut hi = self.chars[self.pos];
+            self.pos += 1;
+            if hi == '\\' {
+                let escaped = match self.peek() {
+                    Some(e) => e,
+                    None => return Err(self.error(start, "unclosed character class")),
+                };
+                self.pos += 1;
+                if self.escape_class_item(escaped).is_some() {
+                    return Err(self.error(hi_pos, "invalid range end"));
+                }
+                hi = self.escape_literal(hi_pos, escaped)?;
+            }
+            if hi < lo {
+                return Err(self.error(item_pos, "character range is out of order"));
+            }
+            class.items.push(ClassItem::Range(lo, hi));
+        }
+        Ok(Node::Class(class))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    // Split and Jump targets are often not known until the code after them
+    // has been emitted, so they get emitted with a placeholder and fixed up.
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.program[at] {
+            Inst::Jump(to) => *to = target,
+            Inst::Split(_, to) => *to = target,
+            _ => unreachable!("only jumps and splits are patched"),
+        }
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Literal(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion));
+            }
+            Node::Group(node, index) => match index {
+                Some(index) => {
+                    self.emit(Inst::Save(index * 2));
+                    self.compile(node);
+                    self.emit(Inst::Save(index * 2 + 1));
+                }
+                None => self.compile(node),
+            },
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node);
+                }
+            }
+            Node::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.emit(Inst::Split(self.program.len() + 1, 0));
+                        self.compile(branch);
+                        jumps.push(self.emit(Inst::Jump(0)));
+                        let next = self.program.len();
+                        self.patch(split, next);
+                    } else {
+                        self.compile(branch);
+                    }
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.patch(jump, end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    None => {
+                        // loop: split body, out; body; jump loop
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jump(split));
+                        let out = self.program.len();
+                        self.program[split] = self.split(split + 1, out, *greedy);
+                    }
+                    Some(max) => {
+                        // Each optional copy may be skipped, which jumps
+                        // past all of the remaining copies.
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let out = self.program.len();
+                        for split in splits {
+                            self.program[split] = self.split(split + 1, out, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // The first target of a split is the preferred one, which is how
+    // greedy and lazy repetition are told apart.
+    fn split(&self, body: usize, out: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, out)
+        } else {
+            Inst::Split(out, body)
+        }
+    }
+}
+
+// The number of instructions that node compiles to, following what
+// Compiler::compile emits. It saturates rather than overflowing, since
+// the point is to catch patterns that are far too big.
+fn program_size(node: &Node) -> usize {
+    match node {
+        Node::Empty => 0,
+        Node::Literal(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+        // Two Saves around a capturing group.
+        Node::Group(node, index) => program_size(node).saturating_add(2 * index.is_some() as usize),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .fold(0usize, |size, node| size.saturating_add(program_size(node))),
+        // A Split and a Jump for every branch but the last.
+        Node::Alternate(branches) => {
+            branches.iter().fold(0usize, |size, branch| {
+                size.saturating_add(program_size(branch)).saturating_add(2)
+            }) - 2
+        }
+        Node::Repeat { node, min, max, .. } => {
+            let size = program_size(node);
+            let optional = match max {
+                // A Split, the body and a Jump back.
+                None => size.saturating_add(2),
+                // A Split before each optional copy.
+                Some(max) => size.saturating_add(1).saturating_mul((max - min) as usize),
+            };
+            size.saturating_mul(*min as usize).saturating_add(optional)
+        }
+    }
+}
+
+// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    slots: usize,
+    case_insensitive: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        Regex::build(pattern, false)
+    }
+
+    // Builds a regex where literals and classes ignore case.
+    pub fn new_case_insensitive(pattern: &str) -> Result<Regex, RegexError> {
+        Regex::build(pattern, true)
+    }
+
+    fn build(pattern: &str, case_insensitive: bool) -> Result<Regex, RegexError> {
+        let (node, groups) = Parser::new(pattern).parse()?;
+        if program_size(&node) > MAX_PROGRAM {
+            return Err(RegexError::new(pattern, 0, "pattern too large"));
+        }
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler.emit(Inst::Save(0));
+        compiler.compile(&node);
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program: compiler.program,
+            slots: groups * 2,
+            case_insensitive,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    // Returns the byte range of the leftmost match in text.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    // Like find, but only considers matches starting at or after start.
+    // Assertions such as ^ and \b still look at the text before start.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let slots = self.run(text, start)?;
+        Some((slots[0]?, slots[1]?))
+    }
+
+    // Like find_at, but also returns where each group in the pattern
+    // matched. The whole match comes first, then the groups in the order
+    // their opening brackets appear. A group that wasn't part of the
+    // match, like the second group of (a)|(b) matching "a", is None.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<(usize, usize)>>> {
+        let slots = self.run(text, start)?;
+        let captures = slots
+            .chunks(2)
+            .map(|pair| Some((pair[0]?, pair[1]?)))
+            .collect();
+        Some(captures)
+    }
+
+    // Returns an iterator over the successive non-overlapping
+    // matches in text, as byte ranges.
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+        Matches {
+            re: self,
+            text,
+            pos: Some(0),
+        }
+    }
+
+    fn run(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            // Start a new attempt at this position, with lower priority than
+            // the attempts that started earlier, until something matches.
+            if matched.is_none() {
+                self.add_thread(&mut current, text, pos, 0, vec![None; self.slots]);
+            }
+            if current.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let c = text[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+            for (pc, slots) in current.threads.drain(..) {
+                let advance = match &self.program[pc] {
+                    Inst::Match => {
+                        // Threads after this one have lower priority, so
+                        // the search for this starting point is over.
+                        matched = Some(slots);
+                        break;
+                    }
+                    Inst::Char(expected) => c.is_some_and(|c| self.char_eq(*expected, c)),
+                    Inst::Any => c.is_some_and(|c| c != '\n'),
+                    Inst::Class(class) => {
+                        c.is_some_and(|c| class.matches(c, self.case_insensitive))
+                    }
+                    _ => unreachable!("only consuming instructions are queued"),
+                };
+                if advance {
+                    self.add_thread(&mut next, text, next_pos, pc + 1, slots);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        matched
+    }
+
+    // Follows the non-consuming instructions from pc and queues the
+    // instructions which need to look at the next character.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        text: &str,
+        pos: usize,
+        pc: usize,
+        slots: Vec<Option<usize>>,
+    ) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((mut pc, mut slots)) = stack.pop() {
+            loop {
+                if threads.visited[pc] {
+                    break;
+                }
+                threads.visited[pc] = true;
+                match &self.program[pc] {
+                    Inst::Jump(to) => pc = *to,
+                    Inst::Split(first, second) => {
+                        stack.push((*second, slots.clone()));
+                        pc = *first;
+                    }
+                    Inst::Save(slot) => {
+                        if *slot < slots.len() {
+                            slots[*slot] = Some(pos);
+                        }
+                        pc += 1;
+                    }
+                    Inst::Assert(assertion) => {
+                        if !assertion_holds(*assertion, text, pos) {
+                            break;
+                        }
+                        pc += 1;
+                    }
+                    _ => {
+                        threads.threads.push((pc, slots));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn char_eq(&self, expected: char, c: char) -> bool {
+        expected == c || (self.case_insensitive && fold(c).eq(fold(expected)))
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+pub struct Matches<'r, 't> {
+    re: &'r Regex,
+    text: &'t str,
+    // Where to look for the next match, or None once we are finished.
+    pos: Option<usize>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let (start, end) = self.re.find_at(self.text, self.pos?)?;
+        self.pos = if start < end {
+            Some(end)
+        } else {
+            // An empty match would be found again at the same place,
+            // so step over the next character before looking again.
+            self.text[end..].chars().next().map(|c| end + c.len_utf8())
+        };
+        Some((start, end))
+    }
+}
+
+struct Threads {
+    threads: Vec<(usize, Vec<Option<usize>>)>,
+    visited: Vec<bool>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            threads: Vec::new(),
+            visited: vec![false; size],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.visited.iter_mut().for_each(|v| *v = false);
+    }
+}
+
+// The characters \w matches and \b looks for the edges of: letters and
+// digits in any script, and '_'.
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn assertion_holds(assertion: Assertion, text: &str, pos: usize) -> bool {
+    let before = text[..pos].chars().next_back();
+    let after = text[pos..].chars().next();
+    match assertion {
+        Assertion::StartLine => before.is_none_or(|c| c == '\n'),
+        Assertion::EndLine => after.is_none_or(|c| c == '\n'),
+        Assertion::WordBoundary | Assertion::NotWordBoundary => {
+            let boundary = before.is_some_and(is_word_char) != after.is_some_and(is_word_char);
+            boundary == (assertion == Assertion::WordBoundary)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_and_anchors() {
+        let re = Regex::new("^err").unwrap();
+        assert!(re.is_match("error: disk full"));
+        assert!(!re.is_match("no error here"));
+
+        let re = Regex::new("full$").unwrap();
+        assert_eq!(re.find("error: disk full"), Some((12, 16)));
+    }
+
+    #[test]
+    fn classes_and_repetition() {
+        let re = Regex::new(r"[0-9]{3}-\d+").unwrap();
+        assert_eq!(re.find("call 555-1234 now"), Some((5, 13)));
+
+        let re = Regex::new("[^a-z ]+").unwrap();
+        assert_eq!(re.find("abc DEF"), Some((4, 7)));
+
+        let re = Regex::new("colou?r").unwrap();
+        assert!(re.is_match("color"));
+        assert!(re.is_match("colour"));
+    }
+
+    #[test]
+    fn alternation_is_leftmost_first() {
+        let re = Regex::new("warn|warning").unwrap();
+        assert_eq!(re.find("a warning"), Some((2, 6)));
+
+        let re = Regex::new(r"(?:GET|POST) /\w+").unwrap();
+        assert_eq!(re.find("POST /login"), Some((0, 11)));
+    }
+
+    #[test]
+    fn lazy_repetition() {
+        let re = Regex::new("<.+>").unwrap();
+        assert_eq!(re.find("<a><b>"), Some((0, 6)));
+
+        let re = Regex::new("<.+?>").unwrap();
+        assert_eq!(re.find("<a><b>"), Some((0, 3)));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let re = Regex::new(r"\bid\b").unwrap();
+        assert!(!re.is_match("valid width"));
+        assert_eq!(re.find("user id=3"), Some((5, 7)));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let re = Regex::new_case_insensitive("rust[a-z]*").unwrap();
+        assert_eq!(re.find("TRUSTY"), Some((1, 6)));
+    }
+
+    #[test]
+    fn captures() {
+        let re = Regex::new(r"(\w+)=(\d+)|(none)").unwrap();
+        assert_eq!(
+            Some(vec![Some((2, 7)), Some((2, 5)), Some((6, 7)), None]),
+            re.captures_at("x key=3", 0)
+        );
+    }
+
+    #[test]
+    fn find_iter() {
+        let re = Regex::new(r"\d+").unwrap();
+        let found: Vec<_> = re.find_iter("a1 b22 c333").collect();
+        assert_eq!(vec![(1, 2), (4, 6), (8, 11)], found);
+
+        let re = Regex::new("x*").unwrap();
+        let found: Vec<_> = re.find_iter("axb").collect();
+        assert_eq!(vec![(0, 0), (1, 2), (2, 2), (3, 3)], found);
+    }
+
+    #[test]
+    fn pathological_pattern_is_fast() {
+        let re = Regex::new("(a*)*b").unwrap();
+        let text = "a".repeat(10_000);
+        assert!(!re.is_match(&text));
+    }
+
+    #[test]
+    fn compile_errors() {
+        let err = Regex::new("abc(def").unwrap_err();
+        assert_eq!(err.position(), 3);
+        assert_eq!(
+            err.to_string(),
+            "invalid regex 'abc(def': unclosed group at position 3"
+        );
+
+        assert!(Regex::new("*abc").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new(r"\q").is_err());
+        assert!(Regex::new("a{5,2}").is_err());
+
+        // Each repetition is within the limit, but together they aren't.
+        let err = Regex::new("((a{1000}){1000}){1000}").unwrap_err();
+        assert!(err.to_string().contains("pattern too large"));
+        assert!(Regex::new("(a{100}){100}").is_ok());
+        // The estimate is exact, apart from the Saves and Match that every
+        // program has.
+        for pattern in ["a|bc|d", "(x)*?", "(?:ab){2,4}", r"\w+[a-c]?$", ""] {
+            let (node, _) = Parser::new(pattern).parse().unwrap();
+            let program = Regex::new(pattern).unwrap().program;
+            assert_eq!(program.len(), program_size(&node) + 3, "{pattern}");
+        }
+    }
+}
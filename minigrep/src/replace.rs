@@ -0,0 +1,261 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::matcher::Matcher;
+use crate::trim_line_ending;
+
+// Search and replace, for --replace and --in-place.
+//
+// The replacement text can refer to what was matched:
+//   $0 or ${0}   the whole match
+//   $1, ${1}...  what the groups in a regex matched, in the order of their
+//                opening brackets; the braces are needed when the number is
+//                followed by a digit, as in ${1}0
+//   $$           a literal '$'
+// A group that didn't take part in the match, or doesn't exist, is
+// replaced with nothing. Any other '$' is left as it is.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Group(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+impl Replacement {
+    pub fn new(template: &str) -> Replacement {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            text.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+            let (group, len) = match after.strip_prefix('{') {
+                Some(braced) => match braced.split_once('}') {
+                    Some((number, _)) => (number.parse().ok(), number.len() + 2),
+                    None => (None, 0),
+                },
+                None => {
+                    let digits =
+                        after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                    (after[..digits].parse().ok(), digits)
+                }
+            };
+            match group {
+                Some(group) => {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Group(group));
+                    rest = &after[len..];
+                }
+                None if after.starts_with('$') => {
+                    text.push('$');
+                    rest = &after[1..];
+                }
+                None => {
+                    text.push('$');
+                    rest = after;
+                }
+            }
+        }
+        text.push_str(rest);
+        parts.push(Part::Text(text));
+        parts.retain(|part| *part != Part::Text(String::new()));
+        Replacement { parts }
+    }
+
+    // Replaces each span in line, returning the new line and where the
+    // replacements ended up in it, so they can be highlighted.
+    pub fn apply(
+        &self,
+        matcher: &Matcher,
+        line: &str,
+        spans: &[(usize, usize)],
+    ) -> (String, Vec<(usize, usize)>) {
+        let mut replaced = String::with_capacity(line.len());
+        let mut new_spans = Vec::with_capacity(spans.len());
+        let mut copied = 0;
+        for &span in spans {
+            replaced.push_str(&line[copied..span.0]);
+            let start = replaced.len();
+            let captures = matcher.captures(line, span);
+            for part in &self.parts {
+                match part {
+                    Part::Text(text) => replaced.push_str(text),
+                    Part::Group(group) => {
+                        if let Some(&Some((start, end))) = captures.get(*group) {
+                            replaced.push_str(&line[start..end]);
+                        }
+                    }
+                }
+            }
+            new_spans.push((start, replaced.len()));
+            copied = span.1;
+        }
+        replaced.push_str(&line[copied..]);
+        (replaced, new_spans)
+    }
+}
+
+// Replaces every match in the file at path, returning how many were
+// replaced. The new contents are written to a temporary file next to the
+// original, which is then renamed over it. A rename within a directory
+// happens all at once, so anything reading the file sees either the old
+// contents or the new, never a half-written mixture, and an error part way
+// through leaves the original alone. Files without any matches aren't
+// touched at all.
+//
+// If backup_suffix is given, a copy of the original is kept under the
+// same name with the suffix added.
+pub fn rewrite_file(
+    path: &Path,
+    matcher: &Matcher,
+    replacement: &Replacement,
+    backup_suffix: Option<&str>,
+) -> io::Result<usize> {
+    // For a symbolic link, the file it points to is what gets changed,
+    // rather than the link being replaced by a file.
+    let path = fs::canonicalize(path)?;
+    let reader = BufReader::new(File::open(&path)?);
+    let temp_path = temp_path(&path);
+    let temp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+
+    let result = (|| {
+        let mut writer = BufWriter::new(temp);
+        let replaced = copy_replacing(reader, &mut writer, matcher, replacement)?;
+        let temp = writer.into_inner().map_err(|e| e.into_error())?;
+        if replaced > 0 {
+            temp.set_permissions(fs::metadata(&path)?.permissions())?;
+            temp.sync_all()?;
+            if let Some(suffix) = backup_suffix {
+                let mut backup = path.clone().into_os_string();
+                backup.push(suffix);
+                fs::copy(&path, backup)?;
+            }
+            fs::rename(&temp_path, &path)?;
+        }
+        Ok(replaced)
+    })();
+    // Whatever happened, the temporary file shouldn't be left lying around.
+    // After a successful rename it is already gone.
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+// Copies reader to writer a line at a time, replacing every match, and
+// returns how many were replaced. Line endings are kept as they were.
+fn copy_replacing(
+    mut reader: impl BufRead,
+    writer: &mut impl Write,
+    matcher: &Matcher,
+    replacement: &Replacement,
+) -> io::Result<usize> {
+    let mut replaced = 0;
+    let mut buffer = String::new();
+    loop {
+        buffer.clear();
+        if reader.read_line(&mut buffer)? == 0 {
+            return Ok(replaced);
+        }
+        let line = trim_line_ending(&buffer);
+        let spans: Vec<(usize, usize)> = matcher
+            .find_all(line)
+            .into_iter()
+            .filter(|(start, end, _)| start < end)
+            .map(|(start, end, _)| (start, end))
+            .collect();
+        if spans.is_empty() {
+            writer.write_all(buffer.as_bytes())?;
+            continue;
+        }
+        replaced += spans.len();
+        let (new_line, _) = replacement.apply(matcher, line, &spans);
+        writer.write_all(new_line.as_bytes())?;
+        writer.write_all(&buffer.as_bytes()[line.len()..])?;
+    }
+}
+
+// A name for the temporary file that is unlikely to clash with anything,
+// in the same directory so that renaming it over the original works.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.minigrep-{}.tmp", process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::Regex;
+    use crate::TempDir;
+
+    #[test]
+    fn parses_templates() {
+        let parts = |template| Replacement::new(template).parts;
+        assert_eq!(
+            vec![
+                Part::Text(String::from("<")),
+                Part::Group(1),
+                Part::Text(String::from("0$")),
+                Part::Group(12),
+                Part::Text(String::from("$x>")),
+            ],
+            parts("<${1}0$$$12$x>")
+        );
+        assert_eq!(vec![Part::Text(String::from("a${b"))], parts("a${b"));
+    }
+
+    #[test]
+    fn replaces_with_groups() {
+        let matcher = Matcher::Regex(Regex::new(r"(\w+)=(\d+)").unwrap());
+        let line = "a=1, bb=22";
+        let spans: Vec<_> = matcher
+            .find_all(line)
+            .iter()
+            .map(|&(s, e, _)| (s, e))
+            .collect();
+        let (replaced, spans) = Replacement::new("$2:$1").apply(&matcher, line, &spans);
+        assert_eq!("1:a, 22:bb", replaced);
+        assert_eq!(vec![(0, 3), (5, 10)], spans);
+
+        let matcher = Matcher::literal("cat");
+        let (replaced, _) = Replacement::new("[$0$1]").apply(&matcher, "a cat", &[(2, 5)]);
+        assert_eq!("a [cat]", replaced);
+    }
+
+    #[test]
+    fn rewrites_files_in_place() {
+        let dir = TempDir::new("replace");
+        let path = dir.join("file.txt");
+        fs::write(&path, "cat\r\ndog\ncat cat").unwrap();
+        let untouched = dir.join("other.txt");
+        fs::write(&untouched, "dog\n").unwrap();
+
+        let matcher = Matcher::literal("cat");
+        let replacement = Replacement::new("cow");
+        assert_eq!(
+            3,
+            rewrite_file(&path, &matcher, &replacement, Some(".bak")).unwrap()
+        );
+        assert_eq!(
+            0,
+            rewrite_file(&untouched, &matcher, &replacement, Some(".bak")).unwrap()
+        );
+
+        assert_eq!("cow\r\ndog\ncow cow", fs::read_to_string(&path).unwrap());
+        assert_eq!(
+            "cat\r\ndog\ncat cat",
+            fs::read_to_string(dir.join("file.txt.bak")).unwrap()
+        );
+        assert!(!dir.join("other.txt.bak").exists());
+        // Nothing else, like a leftover temporary file, is in the directory.
+        assert_eq!(3, fs::read_dir(&dir).unwrap().count());
+    }
+}
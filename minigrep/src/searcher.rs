@@ -0,0 +1,455 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use crate::context::Line;
+use crate::literal::{find_byte, Finder, LinesWith};
+use crate::matcher::Matcher;
+use crate::{Config, Match, Output};
+
+// Searches text from any BufRead, one line or one buffer at a time, so
+// that memory use depends on the length of the longest line and the amount
+// of context asked for rather than on the size of the input.
+#[derive(Clone)]
+pub struct Searcher<'m> {
+    matcher: &'m Matcher,
+    before_context: usize,
+    after_context: usize,
+    // Pass on the lines that don't match instead of those that do, as
+    // with -v.
+    invert_match: bool,
+    // Stop reading after this many matching lines.
+    max_count: Option<usize>,
+}
+
+impl<'m> Searcher<'m> {
+    // before_context and after_context are the number of lines to pass to
+    // the sink before and after each match, as with -B and -A.
+    pub fn new(matcher: &'m Matcher, before_context: usize, after_context: usize) -> Searcher<'m> {
+        Searcher {
+            matcher,
+            before_context,
+            after_context,
+            invert_match: false,
+            max_count: None,
+        }
+    }
+
+    pub fn matcher(&self) -> &'m Matcher {
+        self.matcher
+    }
+
+    // A searcher that does what the config asks for.
+    pub fn for_config(matcher: &'m Matcher, config: &Config) -> Searcher<'m> {
+        let mut searcher = Searcher::new(matcher, config.before_context, config.after_context);
+        searcher.invert_match = config.invert_match;
+        searcher.max_count = config.max_count;
+        match config.output {
+            Output::Lines => {}
+            // Counting doesn't print any lines, so context would be wasted.
+            Output::Count => {
+                searcher.before_context = 0;
+                searcher.after_context = 0;
+            }
+            // Whether a file has a match or not is settled by the first
+            // one, so there's no need to read any further.
+            Output::FilesWithMatches | Output::FilesWithoutMatches => {
+                searcher.before_context = 0;
+                searcher.after_context = 0;
+                searcher.max_count = Some(searcher.max_count.map_or(1, |max| max.min(1)));
+            }
+        }
+        searcher
+    }
+
+    // A searcher for a binary file. Its lines aren't worth printing, so
+    // when they would have been, only whether it matches at all is found.
+    pub fn for_binary(&self, output: Output) -> Searcher<'m> {
+        let mut searcher = self.clone();
+        if output == Output::Lines {
+            searcher.before_context = 0;
+            searcher.after_context = 0;
+            searcher.max_count = Some(1);
+        }
+        searcher
+    }
+
+    // Reads reader to the end and calls sink with each line to be printed,
+    // in order. Lines of context are only passed along when they are near a
+    // match, and a line is never passed twice, so overlapping context windows
+    // come out merged. Returns the number of matching lines.
+    //
+    // Once max_count matching lines have been found, reading stops, apart
+    // from any lines of context still owed after the last match. Lines
+    // that would have matched are passed on as context from then on.
+    pub fn search_reader<R: BufRead>(
+        &self,
+        mut reader: R,
+        mut sink: impl FnMut(Line) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        if let Some(finder) = self.whole_buffer_finder() {
+            return self.search_buffers(finder, reader, sink);
+        }
+        let mut buffer = Vec::new();
+        // The most recent lines that didn't match, held on to in case a
+        // match comes along that they are context for.
+        let mut before: VecDeque<(usize, usize, Vec<u8>)> =
+            VecDeque::with_capacity(self.before_context);
+        let mut after_left = 0;
+        let mut line_number = 0;
+        let mut next_offset = 0;
+        let mut matches = 0;
+
+        loop {
+            let done = self.max_count.is_some_and(|max| matches >= max);
+            if done && after_left == 0 {
+                break;
+            }
+            buffer.clear();
+            let read = reader.read_until(b'\n', &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+            let byte_offset = next_offset;
+            next_offset += read;
+            // Lines are read as bytes, so that a stray byte that isn't
+            // valid UTF-8 doesn't stop the search. The line is searched and
+            // printed with U+FFFD, the replacement character, in its place,
+            // but the byte itself never matches anything, and spans still
+            // point into the bytes that were read.
+            let bytes = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+            let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+            let text = String::from_utf8_lossy(bytes);
+            let line: &str = &text;
+            let raw = matches!(text, Cow::Owned(_)).then_some(bytes);
+
+            let found = match raw {
+                Some(raw) => Match::find_lossy(self.matcher, raw, line, line_number, byte_offset),
+                None => Match::find(self.matcher, line, line_number, byte_offset),
+            };
+            if found.is_none() == self.invert_match && !done {
+                matches += 1;
+                // An inverted match has nothing in the line to point at.
+                let m = found.unwrap_or(Match {
+                    line,
+                    line_number,
+                    byte_offset,
+                    column: 1,
+                    spans: Vec::new(),
+                    patterns: Vec::new(),
+                    distance: None,
+                    raw,
+                });
+                for (line_number, byte_offset, bytes) in before.drain(..) {
+                    let text = String::from_utf8_lossy(&bytes);
+                    sink(Line::Context {
+                        line: &text,
+                        line_number,
+                        byte_offset,
+                        raw: matches!(text, Cow::Owned(_)).then_some(&bytes),
+                    })?;
+                }
+                sink(Line::Match(m))?;
+                after_left = self.after_context;
+            } else if after_left > 0 {
+                after_left -= 1;
+                sink(Line::Context {
+                    line,
+                    line_number,
+                    byte_offset,
+                    raw,
+                })?;
+            } else if self.before_context > 0 {
+                // Reuse the oldest line's buffer once the window is full,
+                // so that we aren't allocating for every line.
+                let mut kept = if before.len() == self.before_context {
+                    before
+                        .pop_front()
+                        .map(|(_, _, kept)| kept)
+                        .unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                kept.clear();
+                kept.extend_from_slice(bytes);
+                before.push_back((line_number, byte_offset, kept));
+            }
+        }
+
+        Ok(matches)
+    }
+
+    // Plain text can be searched for in a whole buffer at a time, with
+    // only the lines it turns up in being split out, which is a lot faster
+    // when most lines don't match. That only works when each matching line
+    // is all that gets printed, without context and without -v.
+    fn whole_buffer_finder(&self) -> Option<&'m Finder> {
+        match self.matcher {
+            Matcher::Literal(finder)
+                if !self.invert_match && self.before_context == 0 && self.after_context == 0 =>
+            {
+                Some(finder)
+            }
+            _ => None,
+        }
+    }
+
+    // Does the same as search_reader, for a searcher that whole_buffer_finder
+    // gives a Finder for. Each buffer the reader fills is searched up to its
+    // last "\n", and the rest is kept back until the line it starts has
+    // been read in full. Most of the time nothing is kept back, so the text
+    // is searched where it is, without being copied.
+    fn search_buffers<R: BufRead>(
+        &self,
+        finder: &Finder,
+        mut reader: R,
+        mut sink: impl FnMut(Line) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        let mut kept = Vec::new();
+        // The number of lines, and of bytes, before the text being searched.
+        let mut line_number = 0;
+        let mut byte_offset = 0;
+        let mut matches = 0;
+
+        loop {
+            let available = reader.fill_buf()?;
+            let end_of_input = available.is_empty();
+            let used = match available.iter().rposition(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None if end_of_input => 0,
+                None => {
+                    kept.extend_from_slice(available);
+                    let read = available.len();
+                    reader.consume(read);
+                    continue;
+                }
+            };
+            let text = if kept.is_empty() {
+                &available[..used]
+            } else {
+                kept.extend_from_slice(&available[..used]);
+                &kept[..]
+            };
+
+            let mut lines = LinesWith::new(finder, text);
+            for (index, start, end, hit) in &mut lines {
+                if self.max_count.is_some_and(|max| matches >= max) {
+                    return Ok(matches);
+                }
+                let bytes = &text[start..end];
+                let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+                // As in search_reader, invalid UTF-8 is decoded lossily.
+                let decoded = String::from_utf8_lossy(bytes);
+                let number = line_number + index + 1;
+                let offset = byte_offset + start;
+                let found = match decoded {
+                    Cow::Owned(_) => {
+                        Match::find_lossy(self.matcher, bytes, &decoded, number, offset)
+                    }
+                    // As in literal::search, the line is only searched
+                    // from the hit on.
+                    Cow::Borrowed(line) => {
+                        let found = finder.find_iter_at(line, hit - start);
+                        let found = found.map(|(start, end)| (start, end, 0));
+                        Match::new(line, number, offset, found.collect())
+                    }
+                };
+                if let Some(m) = found {
+                    matches += 1;
+                    sink(Line::Match(m))?;
+                }
+            }
+            if end_of_input {
+                return Ok(matches);
+            }
+            line_number += lines.line_count();
+            byte_offset += text.len();
+            kept.clear();
+            reader.consume(used);
+        }
+    }
+}
+
+// Binary files are spotted the same way grep does it: text files
+// practically never contain a NUL byte, but most binary formats are full
+// of them. Only the start of the file is checked, which is already in the
+// reader's buffer, so nothing extra has to be read.
+pub fn is_binary(reader: &mut impl BufRead) -> io::Result<bool> {
+    Ok(find_byte(0, reader.fill_buf()?).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(searcher: &Searcher, contents: &str) -> Vec<(usize, bool)> {
+        let mut lines = Vec::new();
+        searcher
+            .search_reader(contents.as_bytes(), |line| {
+                lines.push((line.line_number(), matches!(line, Line::Match(_))));
+                Ok(())
+            })
+            .unwrap();
+        lines
+    }
+
+    #[test]
+    fn matches_the_in_memory_search() {
+        let matcher = Matcher::literal("o");
+        let contents = "one\r\ntwo\nthree\n\nfour";
+        let mut found = Vec::new();
+        Searcher::new(&matcher, 0, 0)
+            .search_reader(contents.as_bytes(), |line| {
+                if let Line::Match(m) = line {
+                    found.push((m.line.to_string(), m.line_number, m.byte_offset, m.spans));
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let expected: Vec<_> = crate::search("o", contents)
+            .into_iter()
+            .map(|m| (m.line.to_string(), m.line_number, m.byte_offset, m.spans))
+            .collect();
+        assert_eq!(expected, found);
+    }
+
+    #[test]
+    fn whole_buffers_give_the_same_lines() {
+        // A buffer this small ends partway through most lines, and can't
+        // hold the longest one at all.
+        let matcher = Matcher::literal("ab");
+        let contents: &[u8] = b"ab\nxx\r\nxxabxxxxxxxx\n\nb\nabab\xe9b\nlast ab";
+        let mut searcher = Searcher::new(&matcher, 0, 0);
+        let search = |searcher: &Searcher| {
+            let mut found = Vec::new();
+            let reader = io::BufReader::with_capacity(4, contents);
+            searcher
+                .search_reader(reader, |line| {
+                    if let Line::Match(m) = line {
+                        found.push((m.line_number, m.byte_offset, m.column, m.spans));
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            found
+        };
+
+        let expected = vec![
+            (1, 0, 1, vec![(0, 2)]),
+            (3, 7, 3, vec![(2, 4)]),
+            (6, 23, 1, vec![(0, 2), (2, 4)]),
+            (7, 30, 6, vec![(5, 7)]),
+        ];
+        assert_eq!(expected, search(&searcher));
+        searcher.max_count = Some(2);
+        assert_eq!(expected[..2], search(&searcher));
+    }
+
+    #[test]
+    fn context_is_streamed_without_repeats() {
+        let matcher = Matcher::literal("match");
+        let contents = "a\nb\nmatch\nc\nmatch\nd\ne\nf\ng\nmatch\nh";
+        let searcher = Searcher::new(&matcher, 2, 1);
+
+        assert_eq!(
+            vec![
+                (1, false),
+                (2, false),
+                (3, true),
+                (4, false),
+                (5, true),
+                (6, false),
+                (8, false),
+                (9, false),
+                (10, true),
+                (11, false),
+            ],
+            collect(&searcher, contents)
+        );
+    }
+
+    #[test]
+    fn counts_matching_lines() {
+        let matcher = Matcher::literal("a");
+        let searcher = Searcher::new(&matcher, 0, 0);
+        let count = searcher
+            .search_reader("a\nb\naa\n".as_bytes(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn inverts_matches() {
+        let matcher = Matcher::literal("a");
+        let mut searcher = Searcher::new(&matcher, 0, 0);
+        searcher.invert_match = true;
+        assert_eq!(
+            vec![(2, true), (4, true)],
+            collect(&searcher, "a\nb\naa\nc\n")
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let search = |matcher: &Matcher, contents: &[u8]| {
+            let mut found = Vec::new();
+            Searcher::new(matcher, 0, 0)
+                .search_reader(contents, |line| {
+                    if let Line::Match(m) = line {
+                        let spans: Vec<_> = m.spans.iter().map(|&s| m.line_span(s)).collect();
+                        found.push((m.line.to_string(), m.column, m.spans, spans));
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            found
+        };
+        // Spans and columns count the bytes that were read, so the "x"
+        // is at byte 5 rather than where it is in the decoded line.
+        let contents: &[u8] = b"c\xff\ncaf\xe9 x\n";
+        assert_eq!(
+            vec![(String::from("caf\u{fffd} x"), 6, vec![(5, 6)], vec![(7, 8)])],
+            search(&Matcher::literal("x"), contents)
+        );
+        // The invalid bytes don't match anything, even U+FFFD itself.
+        assert!(search(&Matcher::literal("\u{fffd}"), contents).is_empty());
+        let matcher = Matcher::Regex(crate::regex::Regex::new("f.").unwrap());
+        assert!(search(&matcher, contents).is_empty());
+    }
+
+    #[test]
+    fn binary_files() {
+        assert!(is_binary(&mut "a\0b\n".as_bytes()).unwrap());
+        assert!(!is_binary(&mut "ab\n".as_bytes()).unwrap());
+
+        let matcher = Matcher::literal("b");
+        let searcher = Searcher::new(&matcher, 0, 0);
+        let contents: &[u8] = b"a\xff\0b\nb\n";
+        let count = searcher
+            .for_binary(Output::Lines)
+            .search_reader(contents, |_| Ok(()))
+            .unwrap();
+        assert_eq!(1, count);
+        let count = searcher
+            .for_binary(Output::Count)
+            .search_reader(contents, |_| Ok(()))
+            .unwrap();
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn stops_after_max_count() {
+        let matcher = Matcher::literal("match");
+        let mut searcher = Searcher::new(&matcher, 0, 2);
+        searcher.max_count = Some(1);
+        // The trailing context is still printed, even the line that would
+        // have been the next match, but nothing more is read.
+        let contents = "match\nmatch\na\nb\nmatch\n";
+        assert_eq!(
+            vec![(1, true), (2, false), (3, false)],
+            collect(&searcher, contents)
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Named groups of file name globs for -t, so that `-t rust` can be used
+// instead of `--include '*.rs'`. More can be added, or existing ones
+// extended, with --type-add NAME:GLOB[,GLOB...].
+
+const PRESETS: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    (
+        "cpp",
+        &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx", "*.h"],
+    ),
+    ("css", &["*.css", "*.scss"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx"]),
+    ("txt", &["*.txt"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Types {
+    types: Vec<(String, Vec<String>)>,
+}
+
+impl Default for Types {
+    fn default() -> Types {
+        let types = PRESETS
+            .iter()
+            .map(|(name, globs)| {
+                (
+                    name.to_string(),
+                    globs.iter().map(|g| g.to_string()).collect(),
+                )
+            })
+            .collect();
+        Types { types }
+    }
+}
+
+impl Types {
+    // Adds the globs in a definition like "web:*.html,*.css" to a type,
+    // creating it if it doesn't exist yet.
+    pub fn add(&mut self, definition: &str) -> Result<(), String> {
+        let (name, globs) = match definition.split_once(':') {
+            Some((name, globs)) if !name.is_empty() && !globs.is_empty() => (name, globs),
+            _ => {
+                return Err(format!(
+                    "invalid file type definition '{definition}': expected NAME:GLOB"
+                ))
+            }
+        };
+        let globs = globs.split(',').map(String::from);
+        match self.types.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => existing.extend(globs),
+            None => self.types.push((name.to_string(), globs.collect())),
+        }
+        Ok(())
+    }
+
+    // The globs for the type with the given name.
+    pub fn globs(&self, name: &str) -> Result<&[String], String> {
+        self.types
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, globs)| globs.as_slice())
+            .ok_or_else(|| format!("unknown file type '{name}'"))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.types.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_and_additions() {
+        let mut types = Types::default();
+        assert_eq!(Ok(&[String::from("*.rs")][..]), types.globs("rust"));
+        types.add("rust:*.rs.in").unwrap();
+        types.add("web:*.html,*.css").unwrap();
+        assert_eq!(2, types.globs("rust").unwrap().len());
+        assert_eq!(2, types.globs("web").unwrap().len());
+        assert_eq!(
+            Err(String::from("unknown file type 'cobol'")),
+            types.globs("cobol")
+        );
+        assert!(types.add("web").is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::MinigrepError;
+use crate::glob::Pattern;
+use crate::ignore::Ignore;
+
+// Options controlling how a directory tree is walked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WalkOptions {
+    // How many directories deep to go below the starting directory.
+    // Files directly inside the starting directory are at depth 1.
+    // None means there is no limit.
+    pub max_depth: Option<usize>,
+    // Symbolic links are skipped unless this is set.
+    pub follow_symlinks: bool,
+    // Files and directories whose names start with '.' are skipped
+    // unless this is set.
+    pub hidden: bool,
+    // Files matched by .gitignore, .minigrepignore or .git/info/exclude
+    // are skipped unless this is set.
+    pub no_ignore: bool,
+    // When there are include patterns, only files matching one of them
+    // are searched. Files matching an exclude pattern never are, and
+    // directories matching an exclude_dir pattern aren't looked inside.
+    // A pattern is matched against the name of the file, or its path
+    // from the starting directory if the pattern contains a '/'.
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub exclude_dir: Vec<Pattern>,
+}
+
+// The names of the ignore files read in each directory, from the lowest
+// priority to the highest.
+const IGNORE_FILES: [&str; 3] = [".git/info/exclude", ".gitignore", ".minigrepignore"];
+
+// An ignore file, along with what the paths it matches are relative to.
+struct ScopedIgnore {
+    ignore: Ignore,
+    // The directory it applies to, in the same form as the walk's paths.
+    base: PathBuf,
+    // For an ignore file in a directory above where the walk started, the
+    // names of the directories between it and the start, which base
+    // can't express.
+    prefix: Vec<String>,
+}
+
+struct Entry {
+    path: PathBuf,
+    depth: usize,
+    // The canonical paths of the directories above this one, used to
+    // spot symlinks that lead back up the tree.
+    ancestors: Vec<PathBuf>,
+    // The ignore files that apply to this entry, lowest priority first.
+    ignores: Vec<Rc<ScopedIgnore>>,
+}
+
+// An iterator over every regular file below a directory.
+// Entries within a directory are visited in sorted order so that the
+// output of a search is the same every time it is run.
+// Problems with individual entries, such as a directory we don't have
+// permission to read, are returned as errors without stopping the walk.
+// Each is a MinigrepError::Io with the path it was about.
+pub struct Walk {
+    options: WalkOptions,
+    root: PathBuf,
+    // Entries still to be visited. The end of the vector is the next one,
+    // so a directory's children are pushed in reverse sorted order.
+    stack: Vec<Entry>,
+}
+
+impl Walk {
+    pub fn new(root: &Path, options: WalkOptions) -> Walk {
+        Walk {
+            options,
+            root: root.to_path_buf(),
+            stack: vec![Entry {
+                path: root.to_path_buf(),
+                depth: 0,
+                ancestors: Vec::new(),
+                ignores: Vec::new(),
+            }],
+        }
+    }
+
+    fn read_dir(&mut self, dir: Entry) -> io::Result<()> {
+        let mut ancestors = dir.ancestors;
+        if self.options.follow_symlinks {
+            let canonical = fs::canonicalize(&dir.path)?;
+            if ancestors.contains(&canonical) {
+                return Err(io::Error::other("recursive directory loop"));
+            }
+            ancestors.push(canonical);
+        }
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&dir.path)? {
+            paths.push(entry?.path());
+        }
+        paths.sort();
+
+        let mut ignores = dir.ignores;
+        if !self.options.no_ignore {
+            if dir.depth == 0 {
+                ignores.extend(parent_ignores(&dir.path));
+            }
+            ignores.extend(read_ignores(&dir.path, &dir.path, Vec::new()));
+        }
+
+        for path in paths.into_iter().rev() {
+            self.stack.push(Entry {
+                path,
+                depth: dir.depth + 1,
+                ancestors: ancestors.clone(),
+                ignores: ignores.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    // Whether the include and exclude patterns let a path through.
+    fn is_selected(&self, path: &Path, is_dir: bool) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let matches_any = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches(&name) || pattern.matches(&relative))
+        };
+        if is_dir {
+            return !matches_any(&self.options.exclude_dir);
+        }
+        (self.options.include.is_empty() || matches_any(&self.options.include))
+            && !matches_any(&self.options.exclude)
+    }
+
+    // Whether the ignore files say to skip an entry. The ones closest to
+    // it are asked first, and the first with an opinion decides.
+    fn is_ignored(entry: &Entry, is_dir: bool) -> bool {
+        entry.ignores.iter().rev().find_map(|scoped| {
+            let relative = entry.path.strip_prefix(&scoped.base).ok()?;
+            let relative: Vec<_> = relative.iter().map(|name| name.to_string_lossy()).collect();
+            let names: Vec<&str> = scoped
+                .prefix
+                .iter()
+                .map(String::as_str)
+                .chain(relative.iter().map(|name| name.as_ref()))
+                .collect();
+            scoped.ignore.matched(&names, is_dir)
+        }) == Some(true)
+    }
+
+    fn is_hidden(path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, MinigrepError>;
+
+    fn next(&mut self) -> Option<Result<PathBuf, MinigrepError>> {
+        loop {
+            let entry = self.stack.pop()?;
+
+            // The starting path is always searched, even if it is hidden
+            // or a symlink, since the user asked for it by name.
+            let metadata = if entry.depth == 0 {
+                fs::metadata(&entry.path)
+            } else {
+                if !self.options.hidden && Walk::is_hidden(&entry.path) {
+                    continue;
+                }
+                match fs::symlink_metadata(&entry.path) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        if !self.options.follow_symlinks {
+                            continue;
+                        }
+                        fs::metadata(&entry.path)
+                    }
+                    other => other,
+                }
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(source) => {
+                    let path = entry.path;
+                    return Some(Err(MinigrepError::Io { path, source }));
+                }
+            };
+
+            if entry.depth > 0 && Walk::is_ignored(&entry, metadata.is_dir()) {
+                continue;
+            }
+            if entry.depth > 0 && !self.is_selected(&entry.path, metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                if self.options.max_depth.is_none_or(|max| entry.depth < max) {
+                    let path = entry.path.clone();
+                    if let Err(source) = self.read_dir(entry) {
+                        return Some(Err(MinigrepError::Io { path, source }));
+                    }
+                }
+            } else if metadata.is_file() {
+                return Some(Ok(entry.path));
+            }
+        }
+    }
+}
+
+// Reads the ignore files in dir, for paths below base.
+fn read_ignores(dir: &Path, base: &Path, prefix: Vec<String>) -> Vec<Rc<ScopedIgnore>> {
+    IGNORE_FILES
+        .iter()
+        .filter_map(|name| Ignore::from_file(&dir.join(name)))
+        .map(|ignore| {
+            Rc::new(ScopedIgnore {
+                ignore,
+                base: base.to_path_buf(),
+                prefix: prefix.clone(),
+            })
+        })
+        .collect()
+}
+
+// When a search starts inside a git repository, the ignore files in the
+// directories above it, up to the top of the repository, apply too, just
+// as they would for git. Outside a repository only the ignore files below
+// the starting directory are used.
+fn parent_ignores(root: &Path) -> Vec<Rc<ScopedIgnore>> {
+    let canonical = match fs::canonicalize(root) {
+        Ok(canonical) => canonical,
+        Err(_) => return Vec::new(),
+    };
+    let mut parents = Vec::new();
+    for dir in canonical.ancestors() {
+        if dir != canonical {
+            parents.push(dir);
+        }
+        if dir.join(".git").exists() {
+            // Those furthest up have the lowest priority, so come first.
+            return parents
+                .into_iter()
+                .rev()
+                .flat_map(|dir: &Path| {
+                    let prefix = canonical
+                        .strip_prefix(dir)
+                        .unwrap_or(Path::new(""))
+                        .iter()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect();
+                    read_ignores(dir, root, prefix)
+                })
+                .collect();
+        }
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    // Builds a fresh directory tree under the system temp directory,
+    // which is removed when the TempDir is dropped.
+    fn tree(name: &str, files: &[&str]) -> TempDir {
+        let root = TempDir::new(&format!("walk-{name}"));
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "contents").unwrap();
+        }
+        root
+    }
+
+    fn walk(root: &Path, options: WalkOptions) -> Vec<String> {
+        Walk::new(root, options)
+            .map(|path| {
+                let path = path.unwrap();
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorted_and_depth_limited() {
+        let root = tree("depth", &["b.txt", "a/z.txt", "a/deep/x.txt", "c.txt"]);
+
+        assert_eq!(
+            vec!["a/deep/x.txt", "a/z.txt", "b.txt", "c.txt"],
+            walk(&root, WalkOptions::default())
+        );
+
+        let options = WalkOptions {
+            max_depth: Some(2),
+            ..WalkOptions::default()
+        };
+        assert_eq!(vec!["a/z.txt", "b.txt", "c.txt"], walk(&root, options));
+    }
+
+    #[test]
+    fn hidden_files() {
+        let root = tree("hidden", &[".secret", ".git/config", "visible"]);
+
+        assert_eq!(vec!["visible"], walk(&root, WalkOptions::default()));
+
+        let options = WalkOptions {
+            hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            vec![".git/config", ".secret", "visible"],
+            walk(&root, options)
+        );
+    }
+
+    #[test]
+    fn ignore_files() {
+        let root = tree(
+            "ignore",
+            &[
+                ".git/info/exclude",
+                ".gitignore",
+                "a.log",
+                "keep.log",
+                "notes.txt",
+                "target/out.txt",
+                "sub/.minigrepignore",
+                "sub/a.txt",
+                "sub/b.txt",
+                "sub/c.log",
+            ],
+        );
+        fs::write(root.join(".git/info/exclude"), "notes.txt\n").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n!keep.log\ntarget/\n").unwrap();
+        fs::write(root.join("sub/.minigrepignore"), "/a.txt\n!c.log\n").unwrap();
+
+        assert_eq!(
+            vec!["keep.log", "sub/b.txt", "sub/c.log"],
+            walk(&root, WalkOptions::default())
+        );
+        // Starting further down, the ignore files above still count.
+        assert_eq!(
+            vec!["b.txt", "c.log"],
+            walk(&root.join("sub"), WalkOptions::default())
+        );
+
+        let options = WalkOptions {
+            no_ignore: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(7, walk(&root, options).len());
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let root = tree(
+            "include",
+            &[
+                "main.rs",
+                "app.min.js",
+                "app.js",
+                "vendor/lib.js",
+                "web/vendor/x.js",
+            ],
+        );
+        let patterns = |globs: &[&str]| globs.iter().map(|g| Pattern::new(g)).collect();
+
+        let options = WalkOptions {
+            include: patterns(&["*.js"]),
+            exclude: patterns(&["*.min.js"]),
+            exclude_dir: patterns(&["vendor"]),
+            ..WalkOptions::default()
+        };
+        assert_eq!(vec!["app.js"], walk(&root, options));
+
+        // A pattern with a '/' is matched against the path instead.
+        let options = WalkOptions {
+            exclude: patterns(&["main.rs"]),
+            exclude_dir: patterns(&["web/vendor"]),
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            vec!["app.js", "app.min.js", "vendor/lib.js"],
+            walk(&root, options)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_detected() {
+        let root = tree("loop", &["dir/file"]);
+        std::os::unix::fs::symlink(&*root, root.join("dir/back")).unwrap();
+
+        // Without following links the loop is never entered.
+        assert_eq!(vec!["dir/file"], walk(&root, WalkOptions::default()));
+
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..WalkOptions::default()
+        };
+        let results: Vec<_> = Walk::new(&root, options).collect();
+        assert_eq!(2, results.len());
+        assert_eq!(
+            format!(
+                "{}: recursive directory loop",
+                root.join("dir/back").display()
+            ),
+            results[0].as_ref().unwrap_err().to_string()
+        );
+        assert_eq!(&root.join("dir/file"), results[1].as_ref().unwrap());
+    }
+}